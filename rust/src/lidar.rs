@@ -1,16 +1,63 @@
 // Run with .command file, --headless
 
 mod argument_parser;
+mod carmen_export;
+mod cdr;
+mod config;
+mod dynamic_obstacles;
+mod export;
+mod g2o_export;
+mod geometry;
+mod labels;
 mod lidar_state;
+mod manifest;
+mod mcap_export;
+mod motion;
+mod npz_export;
+mod occupancy_grid;
+mod odometry;
+mod parquet_export;
+mod planner;
+mod pose_export;
+mod pure_pursuit;
 mod random_geometry;
+mod randomization;
+mod rng;
+mod robot_body;
+mod scan_writer;
+mod sensor;
 mod serializer;
+mod trajectory;
 
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 use std::sync::Mutex;
 
-use crate::lidar::random_geometry::RandomGeometryGenerator;
-use crate::lidar::serializer::write_to_json;
+use crate::lidar::argument_parser::{CliArgs, Settings};
+use crate::lidar::dynamic_obstacles::{Arena, DynamicObstacle, MotionPattern, ObstacleShape};
+use crate::lidar::export::{
+    create_exporter, ExportFormat, ExportOptions, Exporter, RobotRecord, RunRecord, SensorRecord,
+};
+use crate::lidar::geometry::{
+    densify_path, incidence_angle, polygon_edge_normal, ray_polygon_distance,
+    ray_polygon_entry_distance,
+};
+use crate::lidar::labels::{BeamLabel, ObjectClass};
+use crate::lidar::lidar_state::SensorState;
+use crate::lidar::manifest::{IterationRecord, IterationStatus, Manifest, Units};
+use crate::lidar::motion::{
+    Ackermann, DifferentialDrive, Holonomic, MotionKind, MotionModel, Pose2D, VelocityLimits,
+};
+use crate::lidar::occupancy_grid::OccupancyGrid;
+use crate::lidar::odometry::{simulate_odometry, OdometryNoise};
+use crate::lidar::planner::{smooth_path, PlannerKind, SamplingPlanner};
+use crate::lidar::random_geometry::{GeometryConfig, RandomGeometryGenerator};
+use crate::lidar::robot_body::{RobotBody, SelfOcclusion};
+use crate::lidar::scan_writer::ScanWriter;
+use crate::lidar::sensor::SensorConfig;
 use crate::lidar::serializer::SerializableArray2;
+use crate::lidar::trajectory::{
+    boustrophedon_waypoints, loop_closure_pairs, LoopClosure, TrajectoryKind,
+};
 use godot::classes::{
     AStar2D, CollisionPolygon2D, Geometry2D, INode2D, Label, Line2D, Node2D, Polygon2D, RayCast2D,
     RenderingServer, StaticBody2D,
@@ -26,14 +73,72 @@ use std::env;
 pub struct Lidar {
     base: Base<Node2D>,
     _arena: Gd<Polygon2D>,
-    parsed_args: HashMap<String, String>,
+    arguments: BTreeMap<String, String>,
+    settings: BTreeMap<String, String>,
+    randomized: BTreeMap<String, String>,
+    drawn: BTreeMap<String, String>,
     out_dir: String,
     n_iterations: u32,
-    state: lidar_state::LidarState, // Replace individual state variables
+    planner: PlannerKind,
+    trajectory: TrajectoryKind,
+    coverage_spacing: usize,
+    max_exploration_goals: u32,
+    n_loops: u32,
+    n_loop_waypoints: u32,
+    revisit_distance: f32,
+    motion: MotionKind,
+    scan_period: f64,
+    limits: VelocityLimits,
+    wheelbase: f32,
+    max_steering_angle: f32,
+    spin_rate: f32,
+    slew_rate: f32,
+    sensors: Vec<SensorConfig>,
+    merge_scans: bool,
+    robot_body: Option<RobotBody>,
+    self_occlusion: SelfOcclusion,
+    write_labels: bool,
+    write_normals: bool,
+    world_bearings: bool,
+    point_cloud: bool,
+    suppress_lines: bool,
+    object_classes: Vec<ObjectClass>,
+    n_robots: usize,
+    lookahead: f32,
+    astar: Option<Gd<AStar2D>>,
+    free_cells: Vec<bool>,
+    robots: Vec<lidar_state::LidarState>, // One state per robot in the arena
+    geometry: GeometryConfig,
+    n_dynamic_obstacles: usize,
+    dynamic_speed: f32,
+    dynamic_patterns: Vec<MotionPattern>,
+    dynamic_obstacles: Vec<DynamicObstacle>,
+    dynamic_polygons: Vec<Gd<Polygon2D>>,
+    dynamic_arena: Option<Arena>,
+    dynamic_time: f64,
+    export_formats: Vec<ExportFormat>,
+    exporters: Vec<Box<dyn Exporter>>,
+    export_options: ExportOptions,
+    odometry_noise: OdometryNoise,
+    ground_truth_map: OccupancyGrid,
+    seed: u64,
 }
 
-// Static variable declaration outside the struct and impl block
-static LIDAR_COUNT: Mutex<u32> = Mutex::new(0); // Static mutable variable to track instances
+// Grid indices of the start and end points of the path
+const PATH_START_INDEX: i64 = 702;
+const PATH_END_INDEX: i64 = 6290;
+
+// Size of the square body of each robot, as seen by the other robots, if no body is given
+const DEFAULT_BODY_SIZE: f32 = 20.0;
+
+// Number of completed iterations, kept across scene reloads
+static ITERATION_COUNT: Mutex<u32> = Mutex::new(0);
+
+// Seed of the run, chosen on the first iteration
+static RUN_SEED: Mutex<Option<u64>> = Mutex::new(None);
+
+// Every attempted iteration, for the manifest
+static ITERATIONS: Mutex<Vec<IterationRecord>> = Mutex::new(Vec::new());
 
 #[godot_api]
 impl INode2D for Lidar {
@@ -43,15 +148,64 @@ impl INode2D for Lidar {
         Self {
             base,
             _arena: polygon,
-            parsed_args: HashMap::new(),
+            arguments: BTreeMap::new(),
+            settings: BTreeMap::new(),
+            randomized: BTreeMap::new(),
+            drawn: BTreeMap::new(),
             out_dir: String::from("lidar_out"),
             n_iterations: 10,
-            state: lidar_state::LidarState::new(), // Initialize LidarState
+            planner: PlannerKind::AStar,
+            trajectory: TrajectoryKind::Route,
+            coverage_spacing: 5,
+            max_exploration_goals: 100,
+            n_loops: 2,
+            n_loop_waypoints: 4,
+            revisit_distance: 20.0,
+            motion: MotionKind::Waypoint,
+            scan_period: 0.1,
+            limits: VelocityLimits::new(),
+            wheelbase: 30.0,
+            max_steering_angle: 0.5,
+            spin_rate: 0.0,
+            slew_rate: 30.0,
+            sensors: vec![SensorConfig::default_sensor()],
+            merge_scans: false,
+            robot_body: None,
+            self_occlusion: SelfOcclusion::Return,
+            write_labels: false,
+            write_normals: false,
+            world_bearings: false,
+            point_cloud: false,
+            suppress_lines: false,
+            object_classes: Vec::new(),
+            n_robots: 1,
+            lookahead: 30.0,
+            astar: None,
+            free_cells: Vec::new(),
+            robots: Vec::new(),
+            geometry: GeometryConfig::new(),
+            n_dynamic_obstacles: 0,
+            dynamic_speed: 50.0,
+            dynamic_patterns: vec![
+                MotionPattern::LinearBounce,
+                MotionPattern::Waypoints,
+                MotionPattern::RandomWalk,
+            ],
+            dynamic_obstacles: Vec::new(),
+            dynamic_polygons: Vec::new(),
+            dynamic_arena: None,
+            dynamic_time: 0.0,
+            export_formats: Vec::new(),
+            exporters: Vec::new(),
+            export_options: ExportOptions::new(),
+            odometry_noise: OdometryNoise::new(0.05, 0.0005, 0.05, 1.0),
+            ground_truth_map: OccupancyGrid::new(100, 100, 1024. / 100.),
+            seed: 0,
         }
     }
 
     fn ready(&mut self) {
-        godot_print!("Count {}", LIDAR_COUNT.lock().unwrap());
+        godot_print!("Count {}", ITERATION_COUNT.lock().unwrap());
 
         RenderingServer::singleton().set_default_clear_color(Color::from_rgba(
             255. / 255.,
@@ -61,18 +215,134 @@ impl INode2D for Lidar {
         ));
 
         let args: Vec<String> = env::args().collect();
+        let settings = match Settings::resolve(&args) {
+            Ok(settings) => settings,
+            Err(e) => {
+                godot_error!("{}", e);
+                godot_print!("{}", argument_parser::usage());
+                self.quit_early(2);
+                return;
+            }
+        };
 
-        self.parsed_args = argument_parser::parse_args(args);
+        if settings.help {
+            godot_print!("{}", argument_parser::usage());
+            self.quit_early(0);
+            return;
+        }
+
+        godot_print!("Command-line arguments: {:?}", settings.arguments);
+
+        // Each attempt at an iteration, including failed ones, is seeded from the run's seed so
+        // the whole run can be reproduced
+        let run_seed = *RUN_SEED
+            .lock()
+            .unwrap()
+            .get_or_insert_with(|| settings.seed.unwrap_or_else(rand::random));
+        let attempt = ITERATIONS.lock().unwrap().len() as u64;
+        self.seed = run_seed.wrapping_add(attempt);
+        rng::seed(self.seed);
+
+        // The randomized settings are the first draws of the iteration
+        let args = match CliArgs::draw(settings) {
+            Ok(args) => args,
+            Err(e) => {
+                godot_error!("{}", e);
+                self.quit_early(2);
+                return;
+            }
+        };
+        if !args.drawn.is_empty() {
+            godot_print!("Randomized settings: {:?}", args.drawn);
+        }
 
-        godot_print!("Command-line arguments: {:?}", self.parsed_args);
+        if let Some(label) = &args.label {
+            self.add_center_label(label, 1024., 1024.);
+        }
 
-        if let Some(label) = self.parsed_args.get("label") {
-            self.add_center_label(&label.clone(), 1024., 1024.);
+        self.out_dir = args.out_dir;
+        if let Err(e) = std::fs::create_dir_all(&self.out_dir) {
+            godot_error!(
+                "Could not create the output directory {}: {}",
+                self.out_dir,
+                e
+            );
+            self.quit_early(1);
+            return;
         }
 
-        if let Some(n) = self.parsed_args.get("n_iterations") {
-            self.n_iterations = n.parse().unwrap();
+        self.n_iterations = args.n_iterations;
+        self.suppress_lines = args.suppress_lines;
+
+        self.geometry = GeometryConfig {
+            n_shapes: args.n_shapes,
+            min_size: args.min_shape_size,
+            max_size: args.max_shape_size,
+            square_fraction: args.square_fraction,
+            wall_thickness: args.wall_thickness,
+        };
+        self.n_dynamic_obstacles = args.dynamic_obstacles;
+        self.dynamic_speed = args.dynamic_speed;
+        self.dynamic_patterns = args.dynamic_patterns;
+
+        self.planner = args.planner;
+        self.trajectory = args.trajectory;
+        self.coverage_spacing = args.coverage_spacing;
+        self.max_exploration_goals = args.max_exploration_goals;
+        self.n_loops = args.n_loops;
+        self.n_loop_waypoints = args.n_loop_waypoints;
+        self.revisit_distance = args.revisit_distance;
+
+        self.motion = args.motion;
+        self.n_robots = args.n_robots;
+        self.scan_period = args.scan_period;
+        self.limits.max_linear_velocity = args.max_linear_velocity;
+        self.limits.max_angular_velocity = args.max_angular_velocity;
+        self.limits.max_linear_acceleration = args.max_linear_acceleration;
+        self.limits.max_angular_acceleration = args.max_angular_acceleration;
+        self.lookahead = args.lookahead;
+        self.wheelbase = args.wheelbase;
+        self.max_steering_angle = args.max_steering_angle;
+        self.spin_rate = args.spin_rate;
+        self.slew_rate = args.slew_rate;
+        self.robot_body = args.robot_body;
+        self.self_occlusion = args.self_occlusion;
+
+        self.sensors[0].mount = args.sensor_mount;
+        if let Some(sensors) = args.sensors {
+            self.sensors = sensors;
+        }
+
+        self.odometry_noise = args.odometry_noise;
+
+        self.merge_scans = args.merge_scans;
+        self.write_labels = args.labels;
+        self.write_normals = args.normals;
+        self.world_bearings = args.world_bearings;
+        self.point_cloud = args.point_cloud;
+        self.export_formats = args.export.unwrap_or_default();
+        self.export_options.metres_per_pixel = args.metres_per_pixel;
+        self.export_options.carmen_laser = args.carmen_laser;
+        self.exporters = self
+            .export_formats
+            .iter()
+            .map(|format| create_exporter(*format, &self.export_options))
+            .collect();
+
+        let mut settings = args.resolved;
+        settings.insert("seed".to_string(), run_seed.to_string());
+
+        // Echo the settings once, with the seed, so the run can be repeated with --config
+        if attempt == 0 {
+            match config::write(&self.out_dir, &settings) {
+                Ok(filename) => godot_print!("Settings written to {}", filename),
+                Err(e) => godot_error!("Could not write the settings: {}", e),
+            }
         }
+        self.settings = settings;
+        self.arguments = args.arguments;
+        self.randomized = args.randomized;
+        self.drawn = args.drawn;
 
         let geom = self.generate_geometry();
 
@@ -82,126 +352,518 @@ impl INode2D for Lidar {
 
         self.create_astar_grid();
 
-        let path = self.calculate_path(&geom);
-        self.state.path = path;
-        godot_print!("Path length: {}", self.state.path.len());
-        godot_print!("Path (0): {}", self.state.path[0]);
+        // The grid is shared by the robots, exploration resets which points are disabled on every replan
+        let (astar, free) = self.build_astar_grid(&geom);
+        self.astar = Some(astar);
+        self.free_cells = free;
+
+        // Without room for every robot none are placed, and the iteration fails
+        let endpoints = self.choose_robot_endpoints().unwrap_or_default();
+        for (start, end) in endpoints.into_iter() {
+            let mut state = lidar_state::LidarState::new();
+            state.vehicle = self.create_motion_model();
+            state.controller.lookahead = self.lookahead;
+            state.slew_rate = self.slew_rate;
+            state.start_index = start;
+            state.end_index = end;
+            self.robots.push(state);
+        }
 
-        // Copy path into array2 for serialization
-        let path_array = Array2::from_shape_vec(
-            (self.state.path.len(), 2),
-            self.state
-                .path
-                .iter()
-                .flat_map(|v| vec![v.x, v.y])
-                .collect(),
-        )
-        .unwrap();
+        let obstacles: Vec<Vec<Vector2>> = geom
+            .bind()
+            .polygons
+            .iter()
+            .map(|p| p.get_polygon().to_vec())
+            .collect();
+        self.ground_truth_map = OccupancyGrid::from_polygons(100, 100, 1024. / 100., &obstacles);
+
+        for r in 0..self.robots.len() {
+            let mut path = self.calculate_path(&geom, r);
+
+            // Round off corners that are tighter than the vehicle can turn
+            let state = &mut self.robots[r];
+            let turning_radius = state
+                .vehicle
+                .as_ref()
+                .map_or(0.0, |v| v.min_turning_radius());
+            if turning_radius > 0.0 {
+                godot_print!("Smoothing path for turning radius {}", turning_radius);
+                path = smooth_path(&path, turning_radius, &obstacles, 1024. / 100.);
+
+                // Pure pursuit can't follow arcs tighter than its lookahead
+                let controller = &mut state.controller;
+                controller.lookahead = controller.lookahead.max(turning_radius);
+            }
+            state.path = path;
+            godot_print!("Robot {} path length: {}", r, state.path.len());
+            if let Some(first) = state.path.first() {
+                godot_print!("Path (0): {}", first);
+            }
 
-        // Serialize the path to a JSON file
-        let serializable_path = serializer::SerializableArray2 { array: path_array };
+            let points = state.path.clone();
+            for point in points.iter() {
+                self.draw_point(
+                    &point,
+                    Color::from_rgba(255. / 255., 78. / 255., 136. / 255., 1.0),
+                );
+            }
 
-        let count = LIDAR_COUNT.lock().unwrap(); // Lock the mutex before modifying
-        let filename = format!("{}/lidar_path_{}.json", self.out_dir, count);
-        let _ = serializer::write_to_json(&filename, &serializable_path);
+            // Start the robot at the beginning of its path, facing along the first segment
+            if let Some(&start) = points.first() {
+                let heading = points
+                    .iter()
+                    .find(|p| **p != start)
+                    .map_or(0.0, |next| self.get_path_angle(start, *next));
+                self.robots[r].pose = Pose2D::new(start, heading);
+            }
+        }
 
-        let points = self.state.path.clone();
-        for point in points.iter() {
-            self.draw_point(
-                &point,
-                Color::from_rgba(255. / 255., 78. / 255., 136. / 255., 1.0),
+        for static_body in self.create_static_bodies(&geom) {
+            self.base_mut().add_child(static_body);
+        }
+        self.object_classes = geom.bind().classes.clone();
+
+        self.spawn_dynamic_obstacles(obstacles);
+
+        for r in 0..self.robots.len() {
+            self.initialize_rays_and_lines(r);
+
+            // Draw the body whenever it can be seen, by the robot itself or by the others
+            if self.robot_body.is_some() || self.robots.len() > 1 {
+                let body = self.visible_body();
+                let pose = self.robots[r].pose;
+                let mut polygon = Polygon2D::new_alloc();
+                polygon.set_polygon(body.polygon.into());
+                polygon.set_color(Color::from_rgba(118. / 255., 131. / 255., 255. / 255., 1.0));
+                polygon.set_position(pose.position);
+                polygon.set_rotation(pose.heading);
+                self.base_mut().add_child(polygon.clone());
+                self.robots[r].body_polygon = Some(polygon);
+            }
+        }
+
+        self.open_scan_writers();
+    }
+
+    fn process(&mut self, delta: f64) {
+        // The obstacles move by the same time step as the robots
+        let dt = if self.motion != MotionKind::Waypoint {
+            self.scan_period
+        } else {
+            delta
+        };
+        self.step_dynamic_obstacles(dt);
+
+        // Move every robot first, so that each scan sees the others where they are now
+        let mut scans: Vec<(usize, Pose2D)> = Vec::new();
+        for r in 0..self.robots.len() {
+            if self.robots[r].finished {
+                continue;
+            }
+
+            let pose = if self.motion != MotionKind::Waypoint {
+                self.process_vehicle(r)
+            } else {
+                self.process_waypoint(r, delta)
+            };
+            if let Some(pose) = pose {
+                scans.push((r, pose));
+            }
+        }
+
+        for (r, pose) in scans.into_iter() {
+            self.update_rays_and_lines(r, pose);
+        }
+
+        if self.robots.iter().all(|state| state.finished) {
+            self.finish_iteration();
+        }
+    }
+}
+
+// Additional methods for Lidar
+impl Lidar {
+    fn create_motion_model(&self) -> Option<Box<dyn MotionModel>> {
+        match self.motion {
+            MotionKind::Waypoint => None,
+            MotionKind::DiffDrive => Some(Box::new(DifferentialDrive::new(self.limits))),
+            MotionKind::Holonomic => Some(Box::new(Holonomic::new(self.limits, self.spin_rate))),
+            MotionKind::Ackermann => Some(Box::new(Ackermann::new(
+                self.limits,
+                self.wheelbase,
+                self.max_steering_angle,
+            ))),
+        }
+    }
+
+    /// The body other robots see, which is the robot body if one is given.
+    fn visible_body(&self) -> RobotBody {
+        self.robot_body
+            .clone()
+            .unwrap_or_else(|| RobotBody::square(DEFAULT_BODY_SIZE))
+    }
+
+    /// Start and end grid points of each robot's path. The first robot uses the fixed points,
+    /// the others random free points reachable from the first start, spread apart from each other.
+    /// Returns `None` if there isn't room for every robot.
+    fn choose_robot_endpoints(&self) -> Option<Vec<(i64, i64)>> {
+        let mut endpoints = vec![(PATH_START_INDEX, PATH_END_INDEX)];
+        let astar = self.astar.as_ref()?;
+
+        let mut used = vec![PATH_START_INDEX, PATH_END_INDEX];
+        let mut attempts = 0;
+        while endpoints.len() < self.n_robots && attempts < 1000 {
+            attempts += 1;
+
+            // The start and goal are accepted together, so every robot gets both
+            let pair: Vec<i64> = (0..2)
+                .map(|_| (rng::random::<f32>() * self.free_cells.len() as f32) as i64)
+                .collect();
+            let accepted = pair.iter().enumerate().all(|(k, &candidate)| {
+                let position = Self::grid_point(candidate);
+                self.free_cells[candidate as usize]
+                    && used
+                        .iter()
+                        .chain(pair[..k].iter())
+                        .all(|&u| Self::grid_point(u).distance_to(position) > 100.0)
+                    && astar.get_point_path(PATH_START_INDEX, candidate).len() > 1
+            });
+            if accepted {
+                used.extend(pair.iter());
+                endpoints.push((pair[0], pair[1]));
+            }
+        }
+
+        if endpoints.len() < self.n_robots {
+            godot_error!(
+                "Only found room for {} of {} robots",
+                endpoints.len(),
+                self.n_robots
             );
+            return None;
         }
+        Some(endpoints)
+    }
 
-        let static_body = self.create_static_body(&geom);
-        self.base_mut().add_child(static_body);
+    /// Places the dynamic obstacles at random free positions, alternating between pedestrians
+    /// (circles) and carts (rectangles) with a random pattern each.
+    fn spawn_dynamic_obstacles(&mut self, obstacles: Vec<Vec<Vector2>>) {
+        let arena = Arena {
+            min: Vector2::new(0., 0.),
+            max: Vector2::new(1024., 1024.),
+            obstacles,
+            clearance: 10.0,
+        };
+
+        for k in 0..self.n_dynamic_obstacles {
+            let shape = if k % 2 == 0 {
+                ObstacleShape::Circle { radius: 8.0 }
+            } else {
+                ObstacleShape::Rectangle {
+                    length: 30.0,
+                    width: 15.0,
+                }
+            };
+            let pattern = self.dynamic_patterns
+                [(rng::random::<f32>() * self.dynamic_patterns.len() as f32) as usize];
+
+            let Some(obstacle) =
+                DynamicObstacle::random(shape, pattern, self.dynamic_speed, &arena, 4)
+            else {
+                godot_error!("No free space for dynamic obstacle {}", k);
+                continue;
+            };
 
-        // TODO: Align heading with the first segment of the path
+            let mut polygon = Polygon2D::new_alloc();
+            polygon.set_polygon(shape.world_polygon(Pose2D::new(Vector2::ZERO, 0.0)).into());
+            polygon.set_color(Color::from_rgba(64. / 255., 160. / 255., 120. / 255., 1.0));
+            polygon.set_position(obstacle.pose.position);
+            polygon.set_rotation(obstacle.pose.heading);
+            self.base_mut().add_child(polygon.clone());
 
-        self.initialize_rays_and_lines();
+            self.dynamic_obstacles.push(obstacle);
+            self.dynamic_polygons.push(polygon);
+        }
+
+        self.dynamic_arena = Some(arena);
     }
 
-    fn process(&mut self, delta: f64) {
-        if self.state.slewing {
+    /// Moves the dynamic obstacles `dt` seconds along their patterns.
+    fn step_dynamic_obstacles(&mut self, dt: f64) {
+        let Some(arena) = self.dynamic_arena.as_ref() else {
+            return;
+        };
+
+        self.dynamic_time += dt;
+        for (obstacle, polygon) in self
+            .dynamic_obstacles
+            .iter_mut()
+            .zip(self.dynamic_polygons.iter_mut())
+        {
+            obstacle.step(dt as f32, self.dynamic_time, arena);
+            polygon.set_position(obstacle.pose.position);
+            polygon.set_rotation(obstacle.pose.heading);
+        }
+    }
+
+    /// Moves a robot one waypoint along its path, slewing in place first if it has to turn.
+    /// Returns the pose to scan from if the robot moved.
+    fn process_waypoint(&mut self, r: usize, delta: f64) -> Option<Pose2D> {
+        let state = &mut self.robots[r];
+        state.time += delta;
+
+        if state.slewing {
             godot_print!(
                 "Slewing, target {}, angle {}",
-                self.state.target_angle,
-                self.state.angle
+                state.target_angle,
+                state.angle
             );
             // If slewing, calculate the rotation amount based on the slew rate and time delta
-            let rotation_speed = self.state.slew_rate * delta as f32; // degrees per frame based on time delta
-            let angle_diff = self.state.target_angle - self.state.angle;
+            let rotation_speed = state.slew_rate * delta as f32; // radians per frame based on time delta
+            let angle_diff = state.target_angle - state.angle;
             let rotation_step = angle_diff.signum() * rotation_speed.min(angle_diff.abs());
 
             // Update the Lidar's angle
-            self.state.angle += rotation_step;
+            state.angle += rotation_step;
 
             // Check if we have reached the target angle
-            if (self.state.target_angle - self.state.angle).abs() < 1E-4 {
-                self.state.angle = self.state.target_angle; // Snap to target angle
-                self.state.slewing = false; // Finished slewing
+            if (state.target_angle - state.angle).abs() < 1E-4 {
+                state.angle = state.target_angle; // Snap to target angle
+                state.slewing = false; // Finished slewing
             }
+            state.pose.heading = state.angle;
 
             // Update rays' positions and orientations
-            self.update_rays_rotation();
+            self.update_rays_rotation(r);
+            return None;
+        }
+
+        // If not slewing, handle the movement along the path
+        if state.path.is_empty() || state.path_idx >= state.path.len() - 1 {
+            // Exploration keeps choosing new goals until no reachable frontier is left
+            if !(self.trajectory == TrajectoryKind::Exploration && self.extend_exploration_path(r))
+            {
+                self.robots[r].finished = true;
+            }
+            return None;
+        }
+
+        let loc = state.path[state.path_idx];
+        let prev_loc = if state.path_idx > 0 {
+            state.path[state.path_idx - 1]
+        } else {
+            loc
+        };
+
+        let desired_angle = (loc - prev_loc).angle();
+
+        // Check if the Lidar needs to rotate to face the new direction
+        if (state.angle - desired_angle).abs() > 1E-4 {
+            // Start slewing to the desired angle
+            state.slewing = true;
+            state.target_angle = desired_angle;
+            None
         } else {
-            // If not slewing, handle the movement along the path
-            if self.state.path.is_empty() || self.state.path_idx >= self.state.path.len() - 1 {
-                if !self.state.path.is_empty() {
-                    let serializable_arrays: Vec<serializer::SerializableArray2<f64>> = self
-                        .state
-                        .returns
-                        .clone()
-                        .into_iter()
-                        .map(|array| SerializableArray2 { array })
-                        .collect();
+            // Move to the next point in the path
+            state.angle = desired_angle;
+            state.pose = Pose2D::new(loc, desired_angle);
+            state.path_idx += 1;
+            Some(state.pose)
+
+            // Optional: introduce a delay for testing
+            // thread::sleep(time::Duration::from_secs(1));
+        }
+    }
 
-                    let mut count = LIDAR_COUNT.lock().unwrap(); // Lock the mutex before modifying
+    /// Advances a robot's vehicle by one scan period along its path, returning the pose to scan from.
+    fn process_vehicle(&mut self, r: usize) -> Option<Pose2D> {
+        let dt = self.scan_period as f32;
 
-                    let filename = format!("{}/lidar_returns_{}.json", self.out_dir, count);
+        let state = &mut self.robots[r];
+        let mut target = state.controller.target(state.pose, &state.path);
 
-                    let _ = write_to_json(&filename, &serializable_arrays).unwrap();
+        // Exploration keeps choosing new goals until no reachable frontier is left
+        if target.is_none()
+            && self.trajectory == TrajectoryKind::Exploration
+            && self.extend_exploration_path(r)
+        {
+            let state = &mut self.robots[r];
+            target = state.controller.target(state.pose, &state.path);
+        }
 
-                    *count += 1;
+        let state = &mut self.robots[r];
+        let Some(target) = target else {
+            state.finished = true;
+            return None;
+        };
 
-                    if *count >= self.n_iterations {
-                        godot_print!("Finished {} iterations", self.n_iterations);
-                        self.base_mut().get_tree().unwrap().quit();
-                    }
+        let distance_to_goal = state.controller.remaining_distance(state.pose, &state.path);
+        if let Some(vehicle) = state.vehicle.as_mut() {
+            state.pose = vehicle.drive_towards(state.pose, target, distance_to_goal, dt);
+        }
+        state.time += self.scan_period;
+
+        Some(state.pose)
+    }
+
+    /// Writes the outputs of every robot's completed path and reloads the scene for the next iteration.
+    fn finish_iteration(&mut self) {
+        let placed = self.robots.len() == self.n_robots;
+        if placed && self.robots.iter().all(|state| !state.path.is_empty()) {
+            let mut count = ITERATION_COUNT.lock().unwrap(); // Lock the mutex before modifying
+
+            for state in self.robots.iter_mut() {
+                state.odometry = simulate_odometry(&state.scan_poses, self.odometry_noise);
+            }
+
+            let mut files = Vec::new();
+            for r in 0..self.robots.len() {
+                // The path is written at the end, as exploration extends it while running
+                files.push(self.write_path(*count, r));
+                files.extend(self.write_poses(*count, r));
+
+                if self.trajectory == TrajectoryKind::Loop {
+                    files.push(self.write_loop_closures(*count, r));
                 }
 
-                self.base_mut().get_tree().unwrap().reload_current_scene();
-                return;
+                files.extend(self.finish_scan_writers(r, true));
             }
 
-            let loc = self.state.path[self.state.path_idx];
-            let prev_loc = if self.state.path_idx > 0 {
-                self.state.path[self.state.path_idx - 1]
-            } else {
-                loc
-            };
+            if !self.dynamic_obstacles.is_empty() {
+                files.push(self.write_dynamic_tracks(*count));
+            }
 
-            let desired_angle = self.get_path_angle(prev_loc, loc);
+            files.push(self.write_scan_info(*count));
+            files.extend(self.export(*count));
 
-            // Check if the Lidar needs to rotate to face the new direction
-            if (self.state.angle - desired_angle).abs() > 1E-4 {
-                // Start slewing to the desired angle
-                self.state.slewing = true;
-                self.state.target_angle = desired_angle;
-            } else {
-                // Move to the next point in the path
-                self.update_rays_and_lines(loc, prev_loc);
-                self.state.path_idx += 1;
+            self.record_iteration(*count, IterationStatus::Success, files);
+
+            *count += 1;
+
+            if *count >= self.n_iterations {
+                godot_print!("Finished {} iterations", self.n_iterations);
+                self.base_mut().get_tree().unwrap().quit();
+            }
+        } else {
+            // Nothing is written for an iteration without a path for every robot
+            for r in 0..self.robots.len() {
+                self.finish_scan_writers(r, false);
             }
 
-            // Optional: introduce a delay for testing
-            // thread::sleep(time::Duration::from_secs(1));
+            let count = *ITERATION_COUNT.lock().unwrap();
+            self.record_iteration(count, IterationStatus::Failed, Vec::new());
         }
+
+        self.base_mut().get_tree().unwrap().reload_current_scene();
+    }
+
+    /// Writes the iteration in each of the additional export formats, returning the files written.
+    fn export(&self, count: u32) -> Vec<String> {
+        if self.exporters.is_empty() {
+            return Vec::new();
+        }
+
+        let robots = self
+            .robots
+            .iter()
+            .enumerate()
+            .map(|(r, state)| RobotRecord {
+                times: &state.scan_times,
+                poses: &state.scan_poses,
+                odometry: &state.odometry,
+                path: &state.path,
+                loop_closures: self.loop_closures(r),
+                sensors: state
+                    .sensors
+                    .iter()
+                    .map(|sensor| SensorRecord {
+                        config: sensor.config,
+                        returns: &sensor.returns,
+                        labels: &sensor.labels,
+                        poses: &sensor.poses,
+                    })
+                    .collect(),
+            })
+            .collect();
+        let run = RunRecord {
+            out_dir: &self.out_dir,
+            iteration: count,
+            robots,
+            map: &self.ground_truth_map,
+            odometry_noise: self.odometry_noise,
+            return_columns: if self.write_normals { 5 } else { 2 },
+        };
+
+        let mut files = Vec::new();
+        for exporter in self.exporters.iter() {
+            match exporter.export(&run) {
+                Ok(written) => files.extend(written),
+                Err(e) => godot_error!("Export failed: {}", e),
+            }
+        }
+        files
+    }
+
+    /// Adds an iteration to the manifest and rewrites it, so it is complete even if the run stops.
+    fn record_iteration(&self, iteration: u32, status: IterationStatus, files: Vec<String>) {
+        let prefix = format!("{}/", self.out_dir);
+        let mut iterations = ITERATIONS.lock().unwrap();
+        iterations.push(IterationRecord {
+            iteration,
+            status,
+            seed: self.seed,
+            randomized: self.drawn.clone(),
+            frames: self.robots.iter().map(|s| s.scan_poses.len()).collect(),
+            files: files
+                .iter()
+                .map(|f| f.strip_prefix(&prefix).unwrap_or(f).to_string())
+                .collect(),
+        });
+
+        let bearing_frame = if self.world_bearings {
+            "world"
+        } else {
+            "sensor"
+        };
+        let manifest = Manifest {
+            version: manifest::VERSION,
+            git_hash: manifest::GIT_HASH,
+            seed: RUN_SEED.lock().unwrap().unwrap_or(self.seed),
+            arguments: self.arguments.clone(),
+            randomized: self.randomized.clone(),
+            config: serde_json::to_value(config::sections(&self.settings)).unwrap_or_default(),
+            sensors: self
+                .sensors
+                .iter()
+                .map(|sensor| sensor.beams.scan_info(sensor.mount, bearing_frame))
+                .collect(),
+            units: Units {
+                distance: "px",
+                angle: "rad",
+                time: "s",
+                metres_per_pixel: self.export_options.metres_per_pixel,
+            },
+            conventions: manifest::conventions(),
+            files: manifest::file_descriptions(self.write_normals),
+            iterations: iterations.clone(),
+        };
+
+        if let Err(e) = manifest.write(&self.out_dir) {
+            godot_error!("Could not write the manifest: {}", e);
+        }
+    }
+
+    /// Quits before the simulation starts, for bad arguments or `--help`.
+    fn quit_early(&mut self, exit_code: i32) {
+        self.base_mut().set_process(false);
+        self.base_mut()
+            .get_tree()
+            .unwrap()
+            .quit_ex()
+            .exit_code(exit_code)
+            .done();
     }
-}
 
-// Additional methods for Lidar
-impl Lidar {
     fn create_arena_polygon(size_x: f32, size_y: f32) -> Gd<Polygon2D> {
         let mut polygon = Polygon2D::new_alloc();
         let vertices = vec![
@@ -265,7 +927,155 @@ impl Lidar {
         false
     }
 
-    fn calculate_path(&self, geom: &Gd<RandomGeometryGenerator>) -> Vec<Vector2> {
+    fn grid_point(index: i64) -> Vector2 {
+        let i = index % 100;
+        let j = index / 100;
+        Vector2::new(i as f32 * (1024. / 100.), j as f32 * (1024. / 100.))
+    }
+
+    fn grid_index(i: usize, j: usize) -> i64 {
+        (i + 100 * j) as i64
+    }
+
+    fn calculate_path(&self, geom: &Gd<RandomGeometryGenerator>, r: usize) -> Vec<Vector2> {
+        godot_print!(
+            "Generating {:?} trajectory for robot {}",
+            self.trajectory,
+            r
+        );
+
+        match self.trajectory {
+            TrajectoryKind::Route => self.calculate_route(geom, r),
+            TrajectoryKind::Coverage => self.calculate_coverage_path(r),
+            TrajectoryKind::Loop => self.calculate_loop_path(r),
+            TrajectoryKind::Exploration => {
+                // Stay at the start for one scan, then plan towards the frontiers
+                let start = Self::grid_point(self.robots[r].start_index);
+                vec![start, start]
+            }
+        }
+    }
+
+    fn calculate_route(&self, geom: &Gd<RandomGeometryGenerator>, r: usize) -> Vec<Vector2> {
+        godot_print!("Planning path with {:?}", self.planner);
+
+        let state = &self.robots[r];
+        match self.planner {
+            PlannerKind::AStar => self.calculate_astar_path(state.start_index, state.end_index),
+            PlannerKind::RrtStar | PlannerKind::Prm => {
+                let obstacles: Vec<Vec<Vector2>> = geom
+                    .bind()
+                    .polygons
+                    .iter()
+                    .map(|p| p.get_polygon().to_vec())
+                    .collect();
+                let planner = SamplingPlanner::new(
+                    obstacles,
+                    Vector2::new(0., 0.),
+                    Vector2::new(1024., 1024.),
+                );
+
+                let start = Self::grid_point(state.start_index);
+                let end = Self::grid_point(state.end_index);
+                let waypoints = if self.planner == PlannerKind::RrtStar {
+                    planner.rrt_star(start, end)
+                } else {
+                    planner.prm(start, end)
+                };
+
+                // Resample to the A* grid spacing so the Lidar moves at the same rate
+                densify_path(&waypoints, 1024. / 100.)
+            }
+        }
+    }
+
+    fn calculate_astar_path(&self, start: i64, end: i64) -> Vec<Vector2> {
+        let astar = self.astar.as_ref().unwrap();
+
+        // Calculate and return the path from the start point to the end point
+        astar.get_point_path(start, end).to_vec()
+    }
+
+    fn calculate_coverage_path(&self, r: usize) -> Vec<Vector2> {
+        let astar = self.astar.as_ref().unwrap();
+        let free = &self.free_cells;
+
+        // Each robot sweeps its own horizontal band of the arena
+        let n_robots = self.robots.len();
+        let waypoints: Vec<(usize, usize)> =
+            boustrophedon_waypoints(100, 100, self.coverage_spacing, |i, j| free[i + 100 * j])
+                .into_iter()
+                .filter(|&(_, j)| j * n_robots / 100 == r)
+                .collect();
+        godot_print!("Coverage waypoints: {}", waypoints.len());
+
+        let ids: Vec<i64> = waypoints
+            .iter()
+            .map(|&(i, j)| Self::grid_index(i, j))
+            .collect();
+        Self::chain_astar_path(astar, &ids)
+    }
+
+    fn calculate_loop_path(&self, r: usize) -> Vec<Vector2> {
+        let astar = self.astar.as_ref().unwrap();
+        let free = &self.free_cells;
+        let start = self.robots[r].start_index;
+
+        // Pick well separated waypoints that are reachable from the start
+        let mut waypoints: Vec<i64> = Vec::new();
+        let mut attempts = 0;
+        while waypoints.len() < self.n_loop_waypoints as usize && attempts < 1000 {
+            attempts += 1;
+
+            let candidate = (rng::random::<f32>() * free.len() as f32) as i64;
+            if !free[candidate as usize] {
+                continue;
+            }
+            let position = astar.get_point_position(candidate);
+            let separated = waypoints
+                .iter()
+                .chain(std::iter::once(&start))
+                .all(|&w| astar.get_point_position(w).distance_to(position) > 200.0);
+            if separated && astar.get_point_path(start, candidate).len() > 1 {
+                waypoints.push(candidate);
+            }
+        }
+        godot_print!("Loop waypoints: {:?}", waypoints);
+
+        // Go round the waypoints n_loops times, revisiting each one, then return to the start
+        let mut ids = vec![start];
+        for _ in 0..self.n_loops.max(1) {
+            ids.extend_from_slice(&waypoints);
+        }
+        ids.push(start);
+
+        Self::chain_astar_path(astar, &ids)
+    }
+
+    /// Joins grid points with A* paths, skipping any that can't be reached.
+    fn chain_astar_path(astar: &Gd<AStar2D>, ids: &[i64]) -> Vec<Vector2> {
+        let mut path: Vec<Vector2> = Vec::new();
+        let mut current: Option<i64> = None;
+        for &next in ids.iter() {
+            match current {
+                None => {
+                    path.push(astar.get_point_position(next));
+                    current = Some(next);
+                }
+                Some(from) if from != next => {
+                    let segment = astar.get_point_path(from, next).to_vec();
+                    if segment.len() > 1 {
+                        path.extend_from_slice(&segment[1..]);
+                        current = Some(next);
+                    }
+                }
+                Some(_) => {}
+            }
+        }
+        path
+    }
+
+    fn build_astar_grid(&self, geom: &Gd<RandomGeometryGenerator>) -> (Gd<AStar2D>, Vec<bool>) {
         let mut astar = AStar2D::new_gd();
         let mut geometry2d = Geometry2D::singleton();
 
@@ -279,6 +1089,7 @@ impl Lidar {
         }
 
         // Connect points in the grid if they are not occluded by any geometry
+        let mut free = vec![false; 100 * 100];
         for i in 0..100 {
             for j in 0..100 {
                 let index = i + 100 * j;
@@ -286,6 +1097,7 @@ impl Lidar {
                 let y = j as f32 * (1024. / 100.);
 
                 if !self.is_point_occluded(x, y, geom, &mut geometry2d) {
+                    free[index as usize] = true;
                     // Connect to the left neighbor
                     if i > 0 {
                         let left_index = (i - 1) + 100 * j;
@@ -300,8 +1112,324 @@ impl Lidar {
             }
         }
 
-        // Calculate and return the path from point 0 to point 6290 (end point)
-        astar.get_point_path(702, 6290).to_vec()
+        (astar, free)
+    }
+
+    /// Appends a path to the nearest reachable frontier of a robot's scanned map, returning false
+    /// once there is nowhere left to explore.
+    fn extend_exploration_path(&mut self, r: usize) -> bool {
+        if self.robots[r].exploration_goals >= self.max_exploration_goals {
+            godot_print!("Reached {} exploration goals", self.max_exploration_goals);
+            return false;
+        }
+
+        let (Some(mut astar), Some(&current)) = (self.astar.clone(), self.robots[r].path.last())
+        else {
+            return false;
+        };
+
+        // Only plan through cells that have been scanned as free
+        let map = &self.robots[r].map;
+        for j in 0..map.height {
+            for i in 0..map.width {
+                let free = map.get(i, j) == occupancy_grid::FREE;
+                astar
+                    .set_point_disabled_ex(Self::grid_index(i, j))
+                    .disabled(!free)
+                    .done();
+            }
+        }
+
+        let (ci, cj) = map.world_to_cell(current);
+        let from = Self::grid_index(ci.clamp(0, 99) as usize, cj.clamp(0, 99) as usize);
+        astar.set_point_disabled_ex(from).disabled(false).done();
+
+        // Order the frontier clusters by the distance to their closest cell
+        let mut goals: Vec<(f32, (usize, usize))> = map
+            .frontier_clusters(5)
+            .iter()
+            .map(|cluster| {
+                cluster
+                    .iter()
+                    .map(|&(i, j)| (map.cell_to_world(i, j).distance_to(current), (i, j)))
+                    .min_by(|a, b| a.0.total_cmp(&b.0))
+                    .unwrap()
+            })
+            .collect();
+        goals.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        for (_, (i, j)) in goals.iter() {
+            let segment = astar
+                .get_point_path(from, Self::grid_index(*i, *j))
+                .to_vec();
+            if segment.len() > 1 {
+                let state = &mut self.robots[r];
+                state.path.extend_from_slice(&segment[1..]);
+                state.exploration_goals += 1;
+                godot_print!(
+                    "Robot {} exploration goal {}: {} frontiers, path length {}",
+                    r,
+                    state.exploration_goals,
+                    goals.len(),
+                    state.path.len()
+                );
+                return true;
+            }
+        }
+
+        godot_print!("No reachable frontiers left");
+        false
+    }
+
+    /// Ground-truth loop closures between the scans of a robot.
+    fn loop_closures(&self, r: usize) -> Vec<LoopClosure> {
+        let positions: Vec<Vector2> = self.robots[r]
+            .scan_poses
+            .iter()
+            .map(|p| p.position)
+            .collect();
+
+        // Require the robot to have travelled well away before a revisit counts as a loop closure
+        loop_closure_pairs(
+            &positions,
+            self.revisit_distance,
+            10.0 * self.revisit_distance,
+        )
+    }
+
+    fn write_loop_closures(&self, count: u32, r: usize) -> String {
+        let pairs = self.loop_closures(r);
+        godot_print!("Loop closures: {}", pairs.len());
+
+        let filename = self.robot_filename("loop_closures", count, r, "json");
+        let _ = serializer::write_to_json(&filename, &pairs);
+        filename
+    }
+
+    /// Writes the beam layout of each sensor, for reading the bearings of the returns.
+    fn write_scan_info(&self, count: u32) -> String {
+        let bearing_frame = if self.world_bearings {
+            "world"
+        } else {
+            "sensor"
+        };
+        let info: Vec<_> = self
+            .sensors
+            .iter()
+            .map(|sensor| sensor.beams.scan_info(sensor.mount, bearing_frame))
+            .collect();
+
+        let filename = format!("{}/lidar_scan_info_{}.json", self.out_dir, count);
+        let _ = serializer::write_to_json(&filename, &info);
+        filename
+    }
+
+    fn write_dynamic_tracks(&self, count: u32) -> String {
+        let tracks: Vec<_> = self
+            .dynamic_obstacles
+            .iter()
+            .enumerate()
+            .map(|(id, obstacle)| obstacle.track(id))
+            .collect();
+
+        let filename = format!("{}/lidar_dynamic_tracks_{}.json", self.out_dir, count);
+        let _ = serializer::write_to_json(&filename, &tracks);
+        filename
+    }
+
+    /// Suffix numbering the robot in output file names, only when there is more than one.
+    fn robot_suffix(&self, r: usize) -> String {
+        if self.robots.len() > 1 {
+            format!("_robot{}", r)
+        } else {
+            String::new()
+        }
+    }
+
+    /// Name of a per-robot output file.
+    fn robot_filename(&self, kind: &str, count: u32, r: usize, extension: &str) -> String {
+        format!(
+            "{}/lidar_{}_{}{}.{}",
+            self.out_dir,
+            kind,
+            count,
+            self.robot_suffix(r),
+            extension
+        )
+    }
+
+    /// Name of a per-sensor output file, only numbering the sensors when there is more than one.
+    fn sensor_filename(
+        &self,
+        kind: &str,
+        count: u32,
+        r: usize,
+        sensor: usize,
+        extension: &str,
+    ) -> String {
+        if self.sensors.len() > 1 {
+            format!(
+                "{}/lidar_{}_{}{}_sensor{}.{}",
+                self.out_dir,
+                kind,
+                count,
+                self.robot_suffix(r),
+                sensor,
+                extension
+            )
+        } else {
+            self.robot_filename(kind, count, r, extension)
+        }
+    }
+
+    /// Opens the streamed scan outputs of every robot for this iteration.
+    fn open_scan_writers(&mut self) {
+        let count = *ITERATION_COUNT.lock().unwrap();
+        let open = |filename: String| match ScanWriter::create(&filename) {
+            Ok(writer) => Some(writer),
+            Err(e) => {
+                godot_error!("Could not create {}: {}", filename, e);
+                None
+            }
+        };
+
+        for r in 0..self.robots.len() {
+            for k in 0..self.robots[r].sensors.len() {
+                let returns = open(self.sensor_filename("returns", count, r, k, "jsonl"));
+                let labels = if self.write_labels {
+                    open(self.sensor_filename("labels", count, r, k, "jsonl"))
+                } else {
+                    None
+                };
+                let (points, world_points) = if self.point_cloud {
+                    (
+                        open(self.sensor_filename("points", count, r, k, "jsonl")),
+                        open(self.sensor_filename("world_points", count, r, k, "jsonl")),
+                    )
+                } else {
+                    (None, None)
+                };
+
+                let sensor = &mut self.robots[r].sensors[k];
+                sensor.returns_writer = returns;
+                sensor.labels_writer = labels;
+                sensor.points_writer = points;
+                sensor.world_points_writer = world_points;
+            }
+
+            if self.merge_scans {
+                let filename = format!(
+                    "{}/lidar_returns_{}{}_merged.jsonl",
+                    self.out_dir,
+                    count,
+                    self.robot_suffix(r)
+                );
+                self.robots[r].merged_writer = open(filename);
+            }
+        }
+    }
+
+    /// Appends a scan to a streamed output, if it is open.
+    fn stream_scan<T: serde::Serialize>(writer: &mut Option<ScanWriter>, scan: &Array2<T>) {
+        if let Some(w) = writer.as_mut() {
+            if let Err(e) = w.write_scan(scan) {
+                godot_error!("Could not write scan: {}", e);
+            }
+        }
+    }
+
+    /// Closes the streamed scan outputs of a robot, moving them to their final names if `keep`,
+    /// and returns the files kept.
+    fn finish_scan_writers(&mut self, r: usize, keep: bool) -> Vec<String> {
+        let state = &mut self.robots[r];
+        let writers = state
+            .sensors
+            .iter_mut()
+            .flat_map(|sensor| {
+                [
+                    sensor.returns_writer.take(),
+                    sensor.labels_writer.take(),
+                    sensor.points_writer.take(),
+                    sensor.world_points_writer.take(),
+                ]
+            })
+            .chain([state.merged_writer.take()])
+            .flatten();
+
+        let mut files = Vec::new();
+        for writer in writers {
+            let filename = writer.filename().to_string();
+            let result = if keep {
+                writer.finish()
+            } else {
+                writer.discard()
+            };
+            match result {
+                Ok(()) if keep => files.push(filename),
+                Ok(()) => {}
+                Err(e) => godot_error!("Could not finish scan output: {}", e),
+            }
+        }
+        files
+    }
+
+    fn write_poses(&self, count: u32, r: usize) -> Vec<String> {
+        let state = &self.robots[r];
+        let mut files = Vec::new();
+        let filename = self.robot_filename("poses", count, r, "json");
+        self.write_pose_file(&filename, &state.scan_times, &state.scan_poses);
+        files.push(filename);
+
+        let filename = self.robot_filename("odometry", count, r, "json");
+        self.write_pose_file(&filename, &state.scan_times, &state.odometry);
+        files.push(filename);
+
+        for (k, sensor) in state.sensors.iter().enumerate() {
+            let filename = self.sensor_filename("sensor_poses", count, r, k, "json");
+            self.write_pose_file(&filename, &state.scan_times, &sensor.poses);
+            files.push(filename);
+        }
+        files
+    }
+
+    fn write_pose_file(&self, filename: &str, times: &[f64], poses: &[Pose2D]) {
+        // One row of time, x, y, heading per scan
+        let poses_array = Array2::from_shape_vec(
+            (poses.len(), 4),
+            times
+                .iter()
+                .zip(poses.iter())
+                .flat_map(|(t, p)| {
+                    vec![
+                        *t,
+                        p.position.x as f64,
+                        p.position.y as f64,
+                        p.heading as f64,
+                    ]
+                })
+                .collect(),
+        )
+        .unwrap();
+
+        let _ = serializer::write_to_json(filename, &SerializableArray2 { array: poses_array });
+    }
+
+    fn write_path(&self, count: u32, r: usize) -> String {
+        let path = &self.robots[r].path;
+
+        // Copy path into array2 for serialization
+        let path_array = Array2::from_shape_vec(
+            (path.len(), 2),
+            path.iter().flat_map(|v| vec![v.x, v.y]).collect(),
+        )
+        .unwrap();
+
+        // Serialize the path to a JSON file
+        let serializable_path = serializer::SerializableArray2 { array: path_array };
+
+        let filename = self.robot_filename("path", count, r, "json");
+        let _ = serializer::write_to_json(&filename, &serializable_path);
+        filename
     }
 
     fn draw_point(&mut self, point: &Vector2, color: Color) {
@@ -317,139 +1445,334 @@ impl Lidar {
         self.base_mut().add_child(polygon);
     }
 
-    fn create_static_body(&self, geom: &Gd<RandomGeometryGenerator>) -> Gd<StaticBody2D> {
-        let mut static_body = StaticBody2D::new_alloc();
+    /// Creates a static body for each polygon, tagged with the polygon index so that ray hits can be labelled.
+    fn create_static_bodies(&self, geom: &Gd<RandomGeometryGenerator>) -> Vec<Gd<StaticBody2D>> {
+        let mut static_bodies = Vec::new();
         godot_print!("Geoms: {}", geom.bind().polygons.len());
-        for poly in geom.bind().polygons.iter() {
+        for (id, poly) in geom.bind().polygons.iter().enumerate() {
             godot_print!("Adding polygon to static body, {}", poly);
+            let mut static_body = StaticBody2D::new_alloc();
+            static_body.set_meta("object_id".into(), (id as i64).to_variant());
+
             let mut polygon = CollisionPolygon2D::new_alloc();
             polygon.set_polygon(poly.get_polygon());
             godot_print!("pol, {}", poly.get_polygon());
             static_body.add_child(polygon);
+            static_bodies.push(static_body);
         }
-        static_body
+        static_bodies
     }
 
-    fn initialize_rays_and_lines(&mut self) {
-        let n_rays = 360;
-        let d_max = 100000.0;
-        let angles = (0..n_rays).map(|i| i as f32 * 360.0 / n_rays as f32);
-
-        let directions: Vec<Vector2> = angles
-            .map(|angle| {
-                Vector2::new(
-                    d_max * angle.to_radians().cos(),
-                    d_max * angle.to_radians().sin(),
-                )
-            })
-            .collect();
+    /// Label of the static polygon a ray is colliding with.
+    fn static_label(&self, ray: &Gd<RayCast2D>) -> BeamLabel {
+        let Some(collider) = ray.get_collider() else {
+            return BeamLabel::NONE;
+        };
+        let id = collider
+            .get_meta("object_id".into())
+            .try_to::<i64>()
+            .unwrap_or(-1);
+        let class = usize::try_from(id)
+            .ok()
+            .and_then(|i| self.object_classes.get(i).copied())
+            .unwrap_or(ObjectClass::None);
+        BeamLabel::new(id, class)
+    }
 
-        for direction in directions.iter() {
-            let mut ray: Gd<RayCast2D> = RayCast2D::new_alloc();
-            ray.set_position(Vector2::new(100.0, 100.0));
-            ray.set_target_position(*direction);
-            ray.set_collision_mask_value(1, true);
-            ray.set_enabled(true);
+    fn initialize_rays_and_lines(&mut self, r: usize) {
+        for config in self.sensors.clone().iter() {
+            let mut sensor = SensorState::new(*config);
+            let pose = config.mount.sensor_pose(self.robots[r].pose);
+
+            for angle in sensor.beam_angles.clone().iter() {
+                let mut ray: Gd<RayCast2D> = RayCast2D::new_alloc();
+                ray.set_position(pose.position);
+                ray.set_target_position(
+                    Vector2::from_angle(angle + pose.heading) * config.beams.max_range,
+                );
+                ray.set_collision_mask_value(1, true);
+                ray.set_enabled(true);
+
+                if !self.suppress_lines {
+                    let mut line = Line2D::new_alloc();
+                    line.set_width(3.0);
+                    line.add_point(ray.get_position());
+                    line.add_point(ray.get_position());
+                    self.base_mut().add_child(line.clone());
+                    sensor.lines.push(line.clone());
+                }
 
-            if !self.parsed_args.contains_key("suppress_lines") {
-                let mut line = Line2D::new_alloc();
-                line.set_width(3.0);
-                line.add_point(ray.get_position());
-                line.add_point(ray.get_position());
-                self.base_mut().add_child(line.clone());
-                self.state.lines.push(line.clone());
+                self.base_mut().add_child(ray.clone());
+                sensor.rays.push(ray.clone());
             }
 
-            self.base_mut().add_child(ray.clone());
-            self.state.rays.push(ray.clone());
+            self.robots[r].sensors.push(sensor);
         }
     }
 
-    fn update_rays_and_lines(&mut self, loc: Vector2, prev_loc: Vector2) {
-        // Calculate change in angle
-        let angle = self.get_path_angle(prev_loc, loc);
-        let d_angle = angle - self.state.angle;
-        self.state.angle = angle; // Update Lidar heading angle
+    fn update_rays_and_lines(&mut self, r: usize, pose: Pose2D) {
+        let mut merged_points: Vec<Vector2> = Vec::new();
 
-        // Ensure rays are sufficiently long and have correct target positions
-        let mut ray_returns: Array2<f64> = Array2::zeros((360, 2));
+        // The robot body moves with the base and can occlude its own sensors
+        let body = self.robot_body.as_ref().map(|b| b.world_polygon(pose));
+        if let Some(polygon) = self.robots[r].body_polygon.as_mut() {
+            polygon.set_position(pose.position);
+            polygon.set_rotation(pose.heading);
+        }
 
-        for (i, ray) in self.state.rays.clone().iter_mut().enumerate() {
-            // Get the current position of the ray
-            let ray_pos = ray.get_position();
-            let target_pos = ray.get_target_position();
+        // The other robots are moving obstacles, wherever they are this frame
+        let visible_body = self.visible_body();
+        let other_bodies: Vec<(usize, Vec<Vector2>)> = self
+            .robots
+            .iter()
+            .enumerate()
+            .filter(|(o, _)| *o != r)
+            .map(|(o, other)| (o, visible_body.world_polygon(other.pose)))
+            .collect();
+
+        for k in 0..self.robots[r].sensors.len() {
+            // Cast from the sensor, mounted relative to the robot base at `pose`
+            let sensor_pose = self.robots[r].sensors[k].config.mount.sensor_pose(pose);
+            let loc = sensor_pose.position;
+
+            // Point the rays along their beam angles relative to the sensor heading
+            self.robots[r].sensors[k].orient_rays(sensor_pose);
+
+            let n_beams = self.robots[r].sensors[k].rays.len();
+            let beam_angles = self.robots[r].sensors[k].beam_angles.clone();
+            let n_columns = if self.write_normals { 5 } else { 2 };
+            let mut ray_returns: Array2<f64> = Array2::zeros((n_beams, n_columns));
+            let mut beam_labels: Array2<i64> = Array2::zeros((n_beams, 3));
+            let mut endpoints: Vec<(Vector2, bool)> = Vec::with_capacity(n_beams);
+            let mut hit_points: Vec<Vector2> = Vec::new();
+
+            for (i, ray) in self.robots[r].sensors[k]
+                .rays
+                .clone()
+                .iter_mut()
+                .enumerate()
+            {
+                // Cast now rather than waiting for the next physics frame, so the return is for this position
+                ray.force_raycast_update();
+
+                // Check for collision
+                let mut collision_point = if ray.is_colliding() {
+                    ray.get_collision_point()
+                } else {
+                    ray.get_position() + ray.get_target_position()
+                };
+                let mut world_hit = ray.is_colliding();
+                let mut label = if self.write_labels && world_hit {
+                    self.static_label(ray)
+                } else {
+                    BeamLabel::NONE
+                };
+                let mut normal = if world_hit {
+                    Some(ray.get_collision_normal())
+                } else {
+                    None
+                };
+
+                // Other robots and dynamic obstacles in front of the world block the beam like any obstacle
+                let direction = ray.get_target_position().normalized();
+                if let Some((d, moving_label, moving_normal)) = other_bodies
+                    .iter()
+                    .filter_map(|(o, polygon)| {
+                        ray_polygon_distance(loc, direction, polygon).map(|(d, edge)| {
+                            (
+                                d,
+                                BeamLabel::new(*o as i64, ObjectClass::Robot),
+                                polygon_edge_normal(polygon, edge, direction),
+                            )
+                        })
+                    })
+                    .chain(
+                        self.dynamic_obstacles
+                            .iter()
+                            .enumerate()
+                            .filter_map(|(id, o)| {
+                                o.shape.ray_hit(o.pose, loc, direction).map(|(d, n)| {
+                                    (d, BeamLabel::new(id as i64, ObjectClass::Dynamic), n)
+                                })
+                            }),
+                    )
+                    .filter(|(d, _, _)| *d < (collision_point - loc).length())
+                    .min_by(|a, b| a.0.total_cmp(&b.0))
+                {
+                    collision_point = loc + direction * d;
+                    world_hit = true;
+                    label = moving_label;
+                    normal = Some(moving_normal);
+                }
 
-            // Compute vector from ray position to its target
-            let offset = target_pos - ray_pos;
+                // Check whether the robot body is in front of the world, ignoring the edges a
+                // sensor mounted inside the body looks out through
+                let body_hit = body
+                    .as_ref()
+                    .and_then(|polygon| {
+                        ray_polygon_entry_distance(loc, direction, polygon)
+                            .map(|(d, edge)| (d, polygon_edge_normal(polygon, edge, direction)))
+                    })
+                    .filter(|(d, _)| *d < (collision_point - loc).length());
+                let body_distance = body_hit.map(|(d, _)| d);
+
+                // Bearings are relative to the sensor heading, like a real driver, unless asked for in the world frame
+                let ray_angle = if self.world_bearings {
+                    self.get_path_angle(ray.get_position(), collision_point)
+                } else {
+                    beam_angles[i]
+                };
+                let distance = match (body_distance, self.self_occlusion) {
+                    (Some(d), SelfOcclusion::Return) => {
+                        collision_point = loc + direction * d;
+                        merged_points.push(collision_point);
+                        normal = body_hit.map(|(_, n)| n);
+                        d
+                    }
+                    (Some(_), SelfOcclusion::Invalid) => {
+                        collision_point = loc;
+                        normal = None;
+                        f32::NAN
+                    }
+                    (None, _) => {
+                        // Only the world, not the robot itself, goes into the map
+                        endpoints.push((collision_point, world_hit));
+                        merged_points.push(collision_point);
+                        (collision_point - ray.get_position()).length()
+                    }
+                };
+                let hit = world_hit || body_distance.is_some();
+                if hit && distance.is_finite() {
+                    hit_points.push(collision_point);
+                }
+                if body_distance.is_some() {
+                    label = BeamLabel::new(r as i64, ObjectClass::Robot);
+                }
 
-            // Apply rotation matrix to adjust for the new heading
-            let rotated_offset = Vector2::new(
-                offset.x * d_angle.cos() - offset.y * d_angle.sin(),
-                offset.x * d_angle.sin() + offset.y * d_angle.cos(),
-            );
+                // Update ray return data with distance and angle
+                ray_returns[[i, 0]] = distance as f64;
+                ray_returns[[i, 1]] = ray_angle as f64;
+                if self.write_normals {
+                    // The normal and incidence angle are NaN where nothing was hit
+                    let (nx, ny, incidence) = normal.map_or((f32::NAN, f32::NAN, f32::NAN), |n| {
+                        (n.x, n.y, incidence_angle(direction, n))
+                    });
+                    ray_returns[[i, 2]] = nx as f64;
+                    ray_returns[[i, 3]] = ny as f64;
+                    ray_returns[[i, 4]] = incidence as f64;
+                }
+                for (c, value) in label.row().into_iter().enumerate() {
+                    beam_labels[[i, c]] = value;
+                }
 
-            // Update the ray target position relative to its base
-            let new_target_position = ray_pos + rotated_offset;
-            ray.set_target_position(new_target_position);
-            ray.set_position(loc); // Ensure ray position moves with the Lidar
+                if !self.suppress_lines {
+                    // Update visual line representation
+                    let mut line = self.robots[r].sensors[k].lines[i].clone();
+                    line.clear_points();
+                    line.add_point(ray.get_position());
+                    line.add_point(collision_point);
+                    line.set_default_color(if hit {
+                        Color::from_rgba(255. / 255., 140. / 255., 158. / 255., 1.0)
+                    // Red for collision
+                    } else {
+                        Color::from_rgba(0.0, 1.0, 0.0, 1.0) // Green otherwise
+                    });
+                }
+            }
 
-            // Check for collision
-            let collision_point = if ray.is_colliding() {
-                ray.get_collision_point()
-            } else {
-                ray.get_target_position()
-            };
+            // Scans are streamed to the outputs, and only kept for the exporters
+            let keep_scans = !self.exporters.is_empty();
+            let state = &mut self.robots[r];
+            let sensor = &mut state.sensors[k];
+            Self::stream_scan(&mut sensor.returns_writer, &ray_returns);
+            if keep_scans {
+                sensor.returns.push(ray_returns);
+            }
+            if self.write_labels {
+                Self::stream_scan(&mut sensor.labels_writer, &beam_labels);
+                if keep_scans {
+                    sensor.labels.push(beam_labels);
+                }
+            }
+            if self.point_cloud {
+                // The hit points relative to the sensor, and in the world at the ground-truth pose
+                let local: Vec<Vector2> = hit_points
+                    .iter()
+                    .map(|p| (*p - sensor_pose.position).rotated(-sensor_pose.heading))
+                    .collect();
+                Self::stream_scan(&mut sensor.points_writer, &Self::points_array(&local));
+                Self::stream_scan(
+                    &mut sensor.world_points_writer,
+                    &Self::points_array(&hit_points),
+                );
+            }
+            sensor.poses.push(sensor_pose);
 
-            // Update ray return data with distance and angle
-            let distance = (collision_point - ray.get_position()).length();
-            let ray_angle = self.get_path_angle(ray.get_position(), collision_point);
-            ray_returns[[i, 0]] = distance as f64;
-            ray_returns[[i, 1]] = ray_angle as f64;
-
-            if !self.parsed_args.contains_key("suppress_lines") {
-                // Update visual line representation
-                let mut line = self.state.lines[i].clone();
-                line.clear_points();
-                line.add_point(ray.get_position());
-                line.add_point(collision_point);
-                line.set_default_color(if ray.is_colliding() {
-                    Color::from_rgba(255. / 255., 140. / 255., 158. / 255., 1.0)
-                // Red for collision
-                } else {
-                    Color::from_rgba(0.0, 1.0, 0.0, 1.0) // Green otherwise
-                });
+            if self.trajectory == TrajectoryKind::Exploration {
+                state.map.integrate_scan(loc, &endpoints);
             }
         }
 
-        self.state.returns.push(ray_returns);
+        let state = &mut self.robots[r];
+        if self.merge_scans {
+            let merged = Self::merge_in_base_frame(pose, &merged_points);
+            Self::stream_scan(&mut state.merged_writer, &merged);
+        }
+
+        state.scan_poses.push(pose);
+        state.scan_times.push(state.time);
     }
 
-    fn get_path_angle(&self, loc: Vector2, next_loc: Vector2) -> f32 {
-        let diff = next_loc - loc;
-        diff.angle()
+    /// Points as rows of x, y.
+    fn points_array(points: &[Vector2]) -> Array2<f64> {
+        Array2::from_shape_vec(
+            (points.len(), 2),
+            points
+                .iter()
+                .flat_map(|p| vec![p.x as f64, p.y as f64])
+                .collect(),
+        )
+        .unwrap()
     }
 
-    fn update_rays_rotation(&mut self) {
-        let loc = self.state.path[self.state.path_idx]; // Get Lidar's global position
-        let rotation_radians = self.state.angle.to_radians();
+    /// Converts the hit points of every sensor to range and bearing from the robot base, sorted by bearing.
+    fn merge_in_base_frame(base: Pose2D, points: &[Vector2]) -> Array2<f64> {
+        let mut polar: Vec<(f32, f32)> = points
+            .iter()
+            .map(|p| {
+                let local = (*p - base.position).rotated(-base.heading);
+                (local.length(), local.angle())
+            })
+            .collect();
+        polar.sort_by(|a, b| a.1.total_cmp(&b.1));
 
-        for ray in self.state.rays.iter_mut() {
-            let ray_position = ray.get_position();
-            let offset = ray.get_target_position() - ray_position;
+        Array2::from_shape_vec(
+            (polar.len(), 2),
+            polar
+                .iter()
+                .flat_map(|(r, a)| vec![*r as f64, *a as f64])
+                .collect(),
+        )
+        .unwrap()
+    }
 
-            // Rotate each ray's target position
-            let rotated_offset = Vector2::new(
-                offset.x * rotation_radians.cos() - offset.y * rotation_radians.sin(),
-                offset.x * rotation_radians.sin() + offset.y * rotation_radians.cos(),
-            );
+    fn get_path_angle(&self, loc: Vector2, next_loc: Vector2) -> f32 {
+        let diff = next_loc - loc;
+        diff.angle()
+    }
 
-            ray.set_target_position(ray_position + rotated_offset);
-            ray.set_position(loc);
+    fn update_rays_rotation(&mut self, r: usize) {
+        let state = &mut self.robots[r];
+        let loc = state.path[state.path_idx]; // Get Lidar's global position
+        let base_pose = Pose2D::new(loc, state.angle);
+        for sensor in state.sensors.iter_mut() {
+            let sensor_pose = sensor.config.mount.sensor_pose(base_pose);
+            sensor.orient_rays(sensor_pose);
         }
     }
 
     fn generate_geometry(&mut self) -> Gd<RandomGeometryGenerator> {
         // godot_print!("Generating geometry!");
-        random_geometry::RandomGeometryGenerator::new()
+        random_geometry::RandomGeometryGenerator::new(self.geometry)
     }
 }
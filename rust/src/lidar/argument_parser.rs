@@ -1,29 +1,1009 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::f32::consts::FRAC_PI_2;
+use std::fmt::Display;
+use std::str::FromStr;
 
-/// Parses command-line arguments into a HashMap of key-value pairs.
-/// Flags (e.g., `--verbose`) will have an empty string as their value.
-pub fn parse_args(args: Vec<String>) -> HashMap<String, String> {
-    let mut args_map = HashMap::new();
-    let mut iter = args.iter().peekable();
+use crate::lidar::carmen_export::CarmenLaser;
+use crate::lidar::config;
+use crate::lidar::dynamic_obstacles::{parse_pattern_list, MotionPattern};
+use crate::lidar::export::{parse_format_list, ExportFormat};
+use crate::lidar::motion::MotionKind;
+use crate::lidar::odometry::OdometryNoise;
+use crate::lidar::planner::PlannerKind;
+use crate::lidar::randomization;
+use crate::lidar::robot_body::{RobotBody, SelfOcclusion};
+use crate::lidar::sensor::{parse_sensor_list, SensorConfig, SensorMount};
+use crate::lidar::trajectory::TrajectoryKind;
+
+/// A setting of the simulator, given on the command line or in a config file.
+pub struct OptionSpec {
+    pub name: &'static str,
+    /// Section of the config file, empty for options only given on the command line.
+    pub section: &'static str,
+    /// Name of the value in the help, or `None` for a flag.
+    pub value: Option<&'static str>,
+    /// Value used when the option is not given, empty for none.
+    pub default: &'static str,
+    pub help: &'static str,
+}
+
+const fn flag(section: &'static str, name: &'static str, help: &'static str) -> OptionSpec {
+    OptionSpec {
+        name,
+        section,
+        value: None,
+        default: "",
+        help,
+    }
+}
+
+const fn option(
+    section: &'static str,
+    name: &'static str,
+    value: &'static str,
+    default: &'static str,
+    help: &'static str,
+) -> OptionSpec {
+    OptionSpec {
+        name,
+        section,
+        value: Some(value),
+        default,
+        help,
+    }
+}
+
+pub const OPTIONS: &[OptionSpec] = &[
+    flag("", "help", "Print this help and exit"),
+    option("", "config", "FILE", "", "TOML or JSON config file"),
+    option("run", "out_dir", "DIR", "lidar_out", "Output directory"),
+    option(
+        "run",
+        "n_iterations",
+        "N",
+        "10",
+        "Number of iterations to record",
+    ),
+    option(
+        "run",
+        "seed",
+        "N",
+        "",
+        "Seed of the run, random if not given",
+    ),
+    option(
+        "run",
+        "label",
+        "TEXT",
+        "",
+        "Text shown in the centre of the arena",
+    ),
+    flag("run", "suppress_lines", "Do not draw the beams"),
+    option(
+        "arena",
+        "wall_thickness",
+        "PX",
+        "10",
+        "Thickness of the arena walls",
+    ),
+    option(
+        "arena",
+        "dynamic_obstacles",
+        "N",
+        "0",
+        "Number of moving obstacles",
+    ),
+    option(
+        "arena",
+        "dynamic_speed",
+        "PX/S",
+        "50",
+        "Speed of the moving obstacles",
+    ),
+    option(
+        "arena",
+        "dynamic_patterns",
+        "LIST",
+        "bounce,waypoints,random_walk",
+        "Obstacle motions, separated by ','",
+    ),
+    option(
+        "geometry",
+        "n_shapes",
+        "N",
+        "100",
+        "Number of random shapes",
+    ),
+    option(
+        "geometry",
+        "min_shape_size",
+        "PX",
+        "10",
+        "Smallest square side or circle radius",
+    ),
+    option(
+        "geometry",
+        "max_shape_size",
+        "PX",
+        "100",
+        "Largest square side or circle radius",
+    ),
+    option(
+        "geometry",
+        "square_fraction",
+        "F",
+        "0.5",
+        "Fraction of the shapes that are squares",
+    ),
+    option(
+        "planner",
+        "planner",
+        "KIND",
+        "astar",
+        "Path planner: astar, rrt_star or prm",
+    ),
+    option(
+        "planner",
+        "trajectory",
+        "KIND",
+        "route",
+        "Trajectory: route, coverage, exploration or loop",
+    ),
+    option(
+        "planner",
+        "coverage_spacing",
+        "N",
+        "5",
+        "Grid cells between coverage lanes",
+    ),
+    option(
+        "planner",
+        "max_exploration_goals",
+        "N",
+        "100",
+        "Most frontiers visited when exploring",
+    ),
+    option(
+        "planner",
+        "n_loops",
+        "N",
+        "2",
+        "Number of tours of a loop trajectory",
+    ),
+    option(
+        "planner",
+        "n_loop_waypoints",
+        "N",
+        "4",
+        "Waypoints of a loop trajectory",
+    ),
+    option(
+        "planner",
+        "revisit_distance",
+        "PX",
+        "20",
+        "Distance within which a revisit is a loop closure",
+    ),
+    option(
+        "motion",
+        "motion",
+        "KIND",
+        "waypoint",
+        "Motion model: waypoint, diff_drive, holonomic or ackermann",
+    ),
+    option(
+        "motion",
+        "n_robots",
+        "N",
+        "1",
+        "Number of robots sharing the arena",
+    ),
+    option(
+        "motion",
+        "scan_period",
+        "S",
+        "0.1",
+        "Time between scans of the vehicle models",
+    ),
+    option(
+        "motion",
+        "max_linear_velocity",
+        "PX/S",
+        "100",
+        "Maximum forward speed",
+    ),
+    option(
+        "motion",
+        "max_angular_velocity",
+        "RAD/S",
+        "1.5",
+        "Maximum turn rate",
+    ),
+    option(
+        "motion",
+        "max_linear_acceleration",
+        "PX/S2",
+        "200",
+        "Maximum forward acceleration",
+    ),
+    option(
+        "motion",
+        "max_angular_acceleration",
+        "RAD/S2",
+        "3",
+        "Maximum angular acceleration",
+    ),
+    option(
+        "motion",
+        "lookahead",
+        "PX",
+        "30",
+        "Pure pursuit lookahead distance",
+    ),
+    option(
+        "motion",
+        "wheelbase",
+        "PX",
+        "30",
+        "Wheelbase of the Ackermann model",
+    ),
+    option(
+        "motion",
+        "max_steering_angle",
+        "RAD",
+        "0.5",
+        "Steering limit of the Ackermann model",
+    ),
+    option(
+        "motion",
+        "spin_rate",
+        "RAD/S",
+        "0",
+        "Spin of the holonomic model",
+    ),
+    option(
+        "motion",
+        "slew_rate",
+        "RAD/S",
+        "30",
+        "Turn rate of the waypoint motion between path segments",
+    ),
+    option(
+        "motion",
+        "robot_body",
+        "SHAPE",
+        "",
+        "Robot body polygon in the base frame, as x,y vertices separated by ';'",
+    ),
+    option(
+        "motion",
+        "self_occlusion",
+        "MODE",
+        "return",
+        "Beams hitting the body: return or invalid",
+    ),
+    option(
+        "sensor",
+        "sensor_mount",
+        "X,Y,YAW",
+        "0,0,0",
+        "Mount of the default sensor, x forward and y to the right",
+    ),
+    option(
+        "sensor",
+        "sensors",
+        "LIST",
+        "",
+        "Sensors as n_beams,fov,max_range,x,y,yaw, separated by ';'",
+    ),
+    option(
+        "noise",
+        "odometry_noise",
+        "A,B,C,D",
+        "0.05,0.0005,0.05,1.0",
+        "Odometry noise rot_rot,rot_trans,trans_trans,trans_rot",
+    ),
+    flag(
+        "output",
+        "merge_scans",
+        "Also write the scans merged into the base frame",
+    ),
+    flag("output", "labels", "Write the object hit by each beam"),
+    flag(
+        "output",
+        "normals",
+        "Add the surface normal and incidence angle to the returns",
+    ),
+    flag(
+        "output",
+        "world_bearings",
+        "Give bearings relative to the world x axis",
+    ),
+    flag(
+        "output",
+        "point_cloud",
+        "Also write the hits as point clouds",
+    ),
+    option(
+        "output",
+        "export",
+        "LIST",
+        "",
+        "Exports: mcap, carmen, npz, parquet, tum, kitti, g2o, separated by ','",
+    ),
+    option(
+        "output",
+        "metres_per_pixel",
+        "M",
+        "0.01",
+        "Scale of the metric exports",
+    ),
+    option(
+        "output",
+        "carmen_laser",
+        "MESSAGE",
+        "robotlaser",
+        "CARMEN scan message: robotlaser or flaser",
+    ),
+];
+
+/// Finds an option by name.
+pub fn find_option(name: &str) -> Option<&'static OptionSpec> {
+    OPTIONS.iter().find(|o| o.name == name)
+}
+
+/// Godot's own flags, which are skipped before a `--`.
+const GODOT_FLAGS: &[&str] = &[
+    "-h",
+    "--version",
+    "-v",
+    "--verbose",
+    "-q",
+    "--quiet",
+    "--no-header",
+    "-e",
+    "--editor",
+    "-p",
+    "--project-manager",
+    "--quit",
+    "-u",
+    "--upwards",
+    "--headless",
+    "-f",
+    "--fullscreen",
+    "-m",
+    "--maximized",
+    "-w",
+    "--windowed",
+    "-t",
+    "--always-on-top",
+    "--single-window",
+    "-d",
+    "--debug",
+    "--ignore-error-breaks",
+    "--profiling",
+    "--gpu-profile",
+    "--gpu-validation",
+    "--gpu-abort",
+    "--single-threaded-scene",
+    "--debug-collisions",
+    "--debug-paths",
+    "--debug-navigation",
+    "--debug-avoidance",
+    "--debug-stringnames",
+    "--debug-canvas-item-redraw",
+    "--disable-vsync",
+    "--disable-render-loop",
+    "--disable-crash-handler",
+    "--print-fps",
+];
+
+/// Godot's own options that take a value, which are skipped with their value before a `--`.
+const GODOT_OPTIONS: &[&str] = &[
+    "--debug-server",
+    "--quit-after",
+    "-l",
+    "--language",
+    "--path",
+    "--main-pack",
+    "--render-thread",
+    "--remote-fs",
+    "--remote-fs-password",
+    "--audio-driver",
+    "--display-driver",
+    "--audio-output-latency",
+    "--rendering-method",
+    "--rendering-driver",
+    "--gpu-index",
+    "--text-driver",
+    "--tablet-driver",
+    "--log-file",
+    "--write-movie",
+    "--resolution",
+    "--position",
+    "--screen",
+    "--xr-mode",
+    "-b",
+    "--breakpoints",
+    "--remote-debug",
+    "--max-fps",
+    "--frame-delay",
+    "--time-scale",
+    "--fixed-fps",
+    "--delta-smoothing",
+];
+
+/// The simulator's settings, from the command line, the config file and the defaults, before
+/// the randomized ones are drawn.
+#[derive(Clone, Debug)]
+pub struct Settings {
+    /// The options as given on the command line, for the manifest.
+    pub arguments: BTreeMap<String, String>,
+    /// Every setting with a value, with the config file over the defaults and the command line
+    /// over both.
+    pub resolved: BTreeMap<String, String>,
+    /// The settings given as ranges or choices, drawn anew on every iteration.
+    pub randomized: BTreeMap<String, String>,
+    pub help: bool,
+    pub seed: Option<u64>,
+}
+
+impl Settings {
+    /// Parses the process arguments, skipping the executable and Godot's own options. After a
+    /// `--`, which stops Godot reading the arguments, every argument is the simulator's.
+    ///
+    /// Settings not given on the command line are taken from the `--config` file, if any, and
+    /// then from the defaults.
+    pub fn resolve(args: &[String]) -> Result<Self, String> {
+        let arguments = split_options(args)?;
+
+        let mut resolved: BTreeMap<String, String> = OPTIONS
+            .iter()
+            .filter(|o| !o.default.is_empty())
+            .map(|o| (o.name.to_string(), o.default.to_string()))
+            .collect();
+        if let Some(path) = arguments.get("config") {
+            resolved.extend(config::load(path)?);
+        }
+        resolved.extend(arguments.clone());
+
+        // The run's own settings, such as its label, are taken as they are
+        let mut randomized = BTreeMap::new();
+        for (name, value) in resolved.iter() {
+            let fixed = find_option(name).is_some_and(|o| matches!(o.section, "" | "run"));
+            if fixed || !randomization::is_randomized(value) {
+                continue;
+            }
+            randomization::check(value)
+                .map_err(|e| format!("Invalid --{} '{}': {}", name, value, e))?;
+            randomized.insert(name.clone(), value.clone());
+        }
+
+        let settings = Settings {
+            help: resolved.contains_key("help"),
+            seed: parsed(&resolved, "seed")?,
+            arguments,
+            resolved,
+            randomized,
+        };
+        settings.check_randomized()?;
+        Ok(settings)
+    }
+
+    /// Checks the settings with the randomized ones at every combination of their range bounds
+    /// and choices, so that a range reaching invalid values fails before the first iteration
+    /// rather than at whichever iteration draws them.
+    fn check_randomized(&self) -> Result<(), String> {
+        if self.randomized.is_empty() {
+            return Ok(());
+        }
+
+        let mut combinations = vec![BTreeMap::new()];
+        for (name, value) in self.randomized.iter() {
+            combinations = combinations
+                .into_iter()
+                .flat_map(|combination: BTreeMap<String, String>| {
+                    randomization::extremes(value).into_iter().map(move |v| {
+                        let mut combination = combination.clone();
+                        combination.insert(name.clone(), v);
+                        combination
+                    })
+                })
+                .collect();
+        }
+
+        for combination in combinations {
+            let mut values = self.resolved.clone();
+            values.extend(combination.clone());
+            CliArgs::parse_values(self.clone(), &values, BTreeMap::new())
+                .map_err(|e| format!("{}, with the randomized settings at {:?}", e, combination))?;
+        }
+        Ok(())
+    }
+}
+
+/// The settings of an iteration, with the randomized ones drawn.
+///
+/// Settings without a default are `None` when not given.
+#[derive(Debug)]
+pub struct CliArgs {
+    /// The options as given on the command line, for the manifest.
+    pub arguments: BTreeMap<String, String>,
+    /// Every setting with a value, with ranges and choices as given.
+    pub resolved: BTreeMap<String, String>,
+    /// The settings given as ranges or choices.
+    pub randomized: BTreeMap<String, String>,
+    /// The values drawn for them in this iteration.
+    pub drawn: BTreeMap<String, String>,
+    pub out_dir: String,
+    pub n_iterations: u32,
+    pub label: Option<String>,
+    pub suppress_lines: bool,
+    pub wall_thickness: f32,
+    pub dynamic_obstacles: usize,
+    pub dynamic_speed: f32,
+    pub dynamic_patterns: Vec<MotionPattern>,
+    pub n_shapes: u32,
+    pub min_shape_size: f32,
+    pub max_shape_size: f32,
+    pub square_fraction: f32,
+    pub planner: PlannerKind,
+    pub trajectory: TrajectoryKind,
+    pub coverage_spacing: usize,
+    pub max_exploration_goals: u32,
+    pub n_loops: u32,
+    pub n_loop_waypoints: u32,
+    pub revisit_distance: f32,
+    pub motion: MotionKind,
+    pub n_robots: usize,
+    pub scan_period: f64,
+    pub max_linear_velocity: f32,
+    pub max_angular_velocity: f32,
+    pub max_linear_acceleration: f32,
+    pub max_angular_acceleration: f32,
+    pub lookahead: f32,
+    pub wheelbase: f32,
+    pub max_steering_angle: f32,
+    pub spin_rate: f32,
+    pub slew_rate: f32,
+    pub robot_body: Option<RobotBody>,
+    pub self_occlusion: SelfOcclusion,
+    pub sensor_mount: SensorMount,
+    pub sensors: Option<Vec<SensorConfig>>,
+    pub odometry_noise: OdometryNoise,
+    pub merge_scans: bool,
+    pub labels: bool,
+    pub normals: bool,
+    pub world_bearings: bool,
+    pub point_cloud: bool,
+    pub export: Option<Vec<ExportFormat>>,
+    pub metres_per_pixel: f64,
+    pub carmen_laser: CarmenLaser,
+}
+
+impl CliArgs {
+    /// Draws the randomized settings from the shared generator, then parses every setting.
+    pub fn draw(settings: Settings) -> Result<Self, String> {
+        let drawn: BTreeMap<String, String> = settings
+            .randomized
+            .iter()
+            .map(|(name, value)| (name.clone(), randomization::draw(value)))
+            .collect();
+        let mut values = settings.resolved.clone();
+        values.extend(drawn.clone());
+
+        let context = if drawn.is_empty() {
+            String::new()
+        } else {
+            format!(", with the randomized settings drawn as {:?}", drawn)
+        };
+        Self::parse_values(settings, &values, drawn).map_err(|e| format!("{}{}", e, context))
+    }
+
+    fn parse_values(
+        settings: Settings,
+        r: &BTreeMap<String, String>,
+        drawn: BTreeMap<String, String>,
+    ) -> Result<Self, String> {
+        let args = CliArgs {
+            out_dir: value(r, "out_dir")?,
+            n_iterations: value(r, "n_iterations")?,
+            label: r.get("label").cloned(),
+            suppress_lines: r.contains_key("suppress_lines"),
+            wall_thickness: value(r, "wall_thickness")?,
+            dynamic_obstacles: value(r, "dynamic_obstacles")?,
+            dynamic_speed: value(r, "dynamic_speed")?,
+            dynamic_patterns: parsed_with(r, "dynamic_patterns", parse_pattern_list)?
+                .unwrap_or_default(),
+            n_shapes: value(r, "n_shapes")?,
+            min_shape_size: value(r, "min_shape_size")?,
+            max_shape_size: value(r, "max_shape_size")?,
+            square_fraction: value(r, "square_fraction")?,
+            planner: value(r, "planner")?,
+            trajectory: value(r, "trajectory")?,
+            coverage_spacing: value(r, "coverage_spacing")?,
+            max_exploration_goals: value(r, "max_exploration_goals")?,
+            n_loops: value(r, "n_loops")?,
+            n_loop_waypoints: value(r, "n_loop_waypoints")?,
+            revisit_distance: value(r, "revisit_distance")?,
+            motion: value(r, "motion")?,
+            n_robots: value(r, "n_robots")?,
+            scan_period: value(r, "scan_period")?,
+            max_linear_velocity: value(r, "max_linear_velocity")?,
+            max_angular_velocity: value(r, "max_angular_velocity")?,
+            max_linear_acceleration: value(r, "max_linear_acceleration")?,
+            max_angular_acceleration: value(r, "max_angular_acceleration")?,
+            lookahead: value(r, "lookahead")?,
+            wheelbase: value(r, "wheelbase")?,
+            max_steering_angle: value(r, "max_steering_angle")?,
+            spin_rate: value(r, "spin_rate")?,
+            slew_rate: value(r, "slew_rate")?,
+            robot_body: parsed(r, "robot_body")?,
+            self_occlusion: value(r, "self_occlusion")?,
+            sensor_mount: value(r, "sensor_mount")?,
+            sensors: parsed_with(r, "sensors", parse_sensor_list)?,
+            odometry_noise: value(r, "odometry_noise")?,
+            merge_scans: r.contains_key("merge_scans"),
+            labels: r.contains_key("labels"),
+            normals: r.contains_key("normals"),
+            world_bearings: r.contains_key("world_bearings"),
+            point_cloud: r.contains_key("point_cloud"),
+            export: parsed_with(r, "export", parse_format_list)?,
+            metres_per_pixel: value(r, "metres_per_pixel")?,
+            carmen_laser: value(r, "carmen_laser")?,
+            arguments: settings.arguments,
+            resolved: settings.resolved,
+            randomized: settings.randomized,
+            drawn,
+        };
+        args.validate()?;
+        Ok(args)
+    }
+
+    /// Checks the values that parse but make no sense.
+    fn validate(&self) -> Result<(), String> {
+        positive(self.n_iterations, "n_iterations")?;
+        positive(self.wall_thickness, "wall_thickness")?;
+        positive(self.min_shape_size, "min_shape_size")?;
+        positive(self.coverage_spacing, "coverage_spacing")?;
+        positive(self.n_loops, "n_loops")?;
+        positive(self.n_loop_waypoints, "n_loop_waypoints")?;
+        positive(self.revisit_distance, "revisit_distance")?;
+        positive(self.n_robots, "n_robots")?;
+        positive(self.scan_period, "scan_period")?;
+        positive(self.max_linear_velocity, "max_linear_velocity")?;
+        positive(self.max_angular_velocity, "max_angular_velocity")?;
+        positive(self.max_linear_acceleration, "max_linear_acceleration")?;
+        positive(self.max_angular_acceleration, "max_angular_acceleration")?;
+        positive(self.lookahead, "lookahead")?;
+        positive(self.wheelbase, "wheelbase")?;
+        positive(self.max_steering_angle, "max_steering_angle")?;
+        positive(self.slew_rate, "slew_rate")?;
+        positive(self.metres_per_pixel, "metres_per_pixel")?;
+
+        if self.max_shape_size < self.min_shape_size {
+            return Err("Invalid --max_shape_size: smaller than --min_shape_size".to_string());
+        }
+        if self.max_steering_angle >= FRAC_PI_2 {
+            return Err("Invalid --max_steering_angle: must be less than pi/2".to_string());
+        }
+        if !(0.0..=1.0).contains(&self.square_fraction) {
+            return Err("Invalid --square_fraction: must be between 0 and 1".to_string());
+        }
+        if self.sensors.as_ref().is_some_and(|s| s.is_empty()) {
+            return Err("Invalid --sensors: no sensors given".to_string());
+        }
+        if self.sensors.is_some() && self.sensor_mount != SensorMount::new(0.0, 0.0, 0.0) {
+            return Err(
+                "--sensor_mount only applies to the default sensor, give the mounts in --sensors"
+                    .to_string(),
+            );
+        }
+        if self.dynamic_patterns.is_empty() {
+            return Err("Invalid --dynamic_patterns: no patterns given".to_string());
+        }
+        if self.dynamic_speed < 0.0 {
+            return Err("Invalid --dynamic_speed: must not be negative".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Collects the simulator's options and their values, rejecting unknown options and missing
+/// values. Options may be given as `--name value` or `--name=value`.
+fn split_options(args: &[String]) -> Result<BTreeMap<String, String>, String> {
+    let mut given = BTreeMap::new();
+    let mut user_args = false;
+    let mut iter = args.iter().skip(1).peekable();
 
     while let Some(arg) = iter.next() {
-        if arg.starts_with("--") {
-            let key = arg.trim_start_matches('-').to_string();
-
-            // Check if the next argument exists and does not start with '--' (meaning it's a value)
-            if let Some(next_arg) = iter.peek() {
-                if !next_arg.starts_with("--") {
-                    args_map.insert(key, iter.next().unwrap().clone());
-                } else {
-                    // Insert the key with an empty value if it's a flag
-                    args_map.insert(key, String::new());
-                }
-            } else {
-                // Insert the key with an empty value if it's a flag
-                args_map.insert(key, String::new());
+        if arg == "--" || arg == "++" {
+            user_args = true;
+            continue;
+        }
+        if !user_args && GODOT_FLAGS.contains(&arg.as_str()) {
+            continue;
+        }
+        if !user_args && GODOT_OPTIONS.contains(&arg.as_str()) {
+            iter.next();
+            continue;
+        }
+
+        let Some(name) = arg.strip_prefix("--") else {
+            // Godot takes a scene to run as a positional argument
+            if user_args {
+                return Err(format!("Unexpected argument '{}'", arg));
             }
+            continue;
+        };
+        let (name, inline_value) = match name.split_once('=') {
+            Some((name, value)) => (name, Some(value.to_string())),
+            None => (name, None),
+        };
+
+        let Some(spec) = find_option(name) else {
+            return Err(format!("Unknown option '--{}'", name));
+        };
+
+        let value = match (spec.value, inline_value) {
+            (None, None) => String::new(),
+            (None, Some(_)) => return Err(format!("--{} does not take a value", name)),
+            (Some(_), Some(value)) => value,
+            (Some(_), None) => iter
+                .next_if(|next| !next.starts_with("--"))
+                .cloned()
+                .ok_or_else(|| format!("Missing value for --{}", name))?,
+        };
+        given.insert(name.to_string(), value);
+    }
+
+    Ok(given)
+}
+
+/// Parses an option's value, if it was given.
+fn parsed_with<T, E: Display>(
+    given: &BTreeMap<String, String>,
+    name: &str,
+    parse: impl Fn(&str) -> Result<T, E>,
+) -> Result<Option<T>, String> {
+    given
+        .get(name)
+        .map(|value| parse(value).map_err(|e| format!("Invalid --{} '{}': {}", name, value, e)))
+        .transpose()
+}
+
+fn parsed<T: FromStr>(given: &BTreeMap<String, String>, name: &str) -> Result<Option<T>, String>
+where
+    T::Err: Display,
+{
+    parsed_with(given, name, str::parse)
+}
+
+/// Parses the value of an option that has a default.
+fn value<T: FromStr>(given: &BTreeMap<String, String>, name: &str) -> Result<T, String>
+where
+    T::Err: Display,
+{
+    parsed(given, name)?.ok_or_else(|| format!("Missing value for --{}", name))
+}
+
+/// Rejects zero or negative values.
+fn positive<T: PartialOrd + Default + Display>(value: T, name: &str) -> Result<(), String> {
+    if value <= T::default() {
+        return Err(format!("Invalid --{} '{}': must be positive", name, value));
+    }
+    Ok(())
+}
+
+/// The help text listing every option, under the section of the config file it belongs to.
+pub fn usage() -> String {
+    let mut usage = String::from(
+        "Usage: lidar.x86_64 [GODOT OPTIONS] [--] [OPTIONS]\n\n\
+         Godot's own options, such as --headless, are passed to Godot. As Godot handles --help \
+         itself, use `-- --help` for this help.\n\n\
+         Every option but --help and --config can also be set in the config file, in the \
+         [section] it is listed under, with the command line taking precedence.\n\n\
+         Outside [run], any value, or value of a list, can be a range `min..max` or a set of \
+         choices `a|b|c`, drawn anew on every iteration.\n\nOptions:\n",
+    );
+    let mut section = "";
+    for spec in OPTIONS {
+        if spec.section != section {
+            section = spec.section;
+            usage.push_str(&format!("[{}]\n", section));
         }
+
+        let option = match spec.value {
+            Some(value) => format!("--{} {}", spec.name, value),
+            None => format!("--{}", spec.name),
+        };
+        let default = if spec.default.is_empty() {
+            String::new()
+        } else {
+            format!(" ({})", spec.default)
+        };
+        usage.push_str(&format!("  {:<36} {}{}\n", option, spec.help, default));
+    }
+    usage
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<CliArgs, String> {
+        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        CliArgs::draw(Settings::resolve(&args)?)
     }
 
-    args_map
+    #[test]
+    fn defaults_apply_when_nothing_is_given() {
+        let args = parse(&["lidar"]).unwrap();
+        assert_eq!(args.n_iterations, 10);
+        assert_eq!(args.out_dir, "lidar_out");
+        assert_eq!(args.planner, PlannerKind::AStar);
+        assert!(!args.labels);
+        assert!(args.sensors.is_none());
+        assert!(args.arguments.is_empty());
+    }
+
+    #[test]
+    fn godot_options_are_skipped() {
+        let args = parse(&[
+            "lidar",
+            "--headless",
+            "--path",
+            ".",
+            "--n_iterations=3",
+            "--",
+            "--labels",
+            "--planner",
+            "prm",
+        ])
+        .unwrap();
+        assert_eq!(args.n_iterations, 3);
+        assert!(args.labels);
+        assert_eq!(args.planner, PlannerKind::Prm);
+        assert_eq!(args.arguments.len(), 3);
+    }
+
+    #[test]
+    fn bad_options_are_rejected() {
+        for (args, error) in [
+            (&["lidar", "--bogus"][..], "Unknown option '--bogus'"),
+            (&["lidar", "--seed"][..], "Missing value for --seed"),
+            (
+                &["lidar", "--labels=1"][..],
+                "--labels does not take a value",
+            ),
+            (&["lidar", "--", "stray"][..], "Unexpected argument 'stray'"),
+            (&["lidar", "--n_robots", "0"][..], "Invalid --n_robots '0'"),
+            (
+                &["lidar", "--n_shapes", "many"][..],
+                "Invalid --n_shapes 'many'",
+            ),
+            (
+                &["lidar", "--max_shape_size", "5"][..],
+                "smaller than --min_shape_size",
+            ),
+            (&["lidar", "--square_fraction", "2"][..], "between 0 and 1"),
+        ] {
+            let e = parse(args).unwrap_err();
+            assert!(e.contains(error), "{:?}: {}", args, e);
+        }
+    }
+
+    #[test]
+    fn motion_limits_are_validated() {
+        for option in [
+            "max_linear_velocity",
+            "max_angular_velocity",
+            "max_linear_acceleration",
+            "max_angular_acceleration",
+            "lookahead",
+            "wheelbase",
+            "max_steering_angle",
+        ] {
+            for value in ["0", "-1"] {
+                let e = parse(&["lidar", &format!("--{}", option), value]).unwrap_err();
+                assert!(
+                    e.contains("must be positive"),
+                    "--{} {}: {}",
+                    option,
+                    value,
+                    e
+                );
+            }
+        }
+        let e = parse(&["lidar", "--max_steering_angle", "1.6"]).unwrap_err();
+        assert!(e.contains("less than pi/2"), "{}", e);
+
+        // The holonomic model may spin either way, or not at all
+        assert!(parse(&["lidar", "--spin_rate", "-0.5"]).is_ok());
+    }
+
+    #[test]
+    fn sensors_are_validated() {
+        let args = parse(&[
+            "lidar",
+            "--sensors",
+            "90,3.14,1000,0,0,0;45,1.5,500,10,0,3.14",
+        ]);
+        assert_eq!(args.unwrap().sensors.unwrap().len(), 2);
+
+        for sensors in [
+            "0,3.14,1000,0,0,0",
+            "90,0,1000,0,0,0",
+            "90,3.14,0,0,0,0",
+            "90,3.14",
+        ] {
+            let e = parse(&["lidar", "--sensors", sensors]).unwrap_err();
+            assert!(e.starts_with("Invalid --sensors"), "{}: {}", sensors, e);
+        }
+
+        let e = parse(&[
+            "lidar",
+            "--sensors",
+            "90,3.14,1000,0,0,0",
+            "--sensor_mount",
+            "10,0,0",
+        ])
+        .unwrap_err();
+        assert!(e.contains("--sensor_mount only applies to the default sensor"));
+    }
+
+    #[test]
+    fn randomized_settings_are_drawn_from_the_seed() {
+        let args: Vec<String> = ["lidar", "--n_shapes", "20..150", "--planner", "astar|prm"]
+            .iter()
+            .map(|a| a.to_string())
+            .collect();
+        let draw = |seed| {
+            crate::lidar::rng::seed(seed);
+            CliArgs::draw(Settings::resolve(&args).unwrap()).unwrap()
+        };
+
+        let (first, again) = (draw(7), draw(7));
+        assert_eq!(first.drawn, again.drawn);
+        assert!((20..=150).contains(&first.n_shapes));
+        assert_eq!(first.resolved["n_shapes"], "20..150");
+
+        let e = parse(&["lidar", "--n_shapes", "150..20"]).unwrap_err();
+        assert!(e.contains("is empty"), "{}", e);
+        let args = parse(&["lidar", "--label", "a|b"]).unwrap();
+        assert!(args.drawn.is_empty());
+    }
+
+    #[test]
+    fn randomized_settings_are_checked_before_they_are_drawn() {
+        let resolve = |args: &[&str]| {
+            Settings::resolve(&args.iter().map(|a| a.to_string()).collect::<Vec<_>>())
+        };
+
+        // Only some draws of the range are larger than the maximum
+        let e = resolve(&[
+            "lidar",
+            "--min_shape_size",
+            "5..200",
+            "--max_shape_size",
+            "100",
+        ])
+        .unwrap_err();
+        assert!(e.contains("smaller than --min_shape_size"), "{}", e);
+        assert!(e.contains(r#""min_shape_size": "200""#), "{}", e);
+
+        for bad in [
+            &["lidar", "--planner", "astar|dijkstra"][..],
+            &["lidar", "--n_robots", "0..2"][..],
+            &["lidar", "--n_shapes", "10..20.5"][..],
+            &[
+                "lidar",
+                "--sensors",
+                "360,6.28,1000,0,0,0;0|90,3.14,500,0,0,0",
+            ][..],
+        ] {
+            assert!(resolve(bad).is_err(), "{:?}", bad);
+        }
+        assert!(resolve(&["lidar", "--min_shape_size", "5..50"]).is_ok());
+    }
 }
@@ -0,0 +1,197 @@
+use std::fs::File;
+use std::io::{BufWriter, Result as IoResult, Write};
+use std::str::FromStr;
+
+use crate::lidar::export::{Exporter, MetricPose, RobotRecord, RunRecord};
+use crate::lidar::sensor::BeamModel;
+use ndarray::Array2;
+
+const HOSTNAME: &str = "sim2dld";
+
+const LOG_HEADER: &str = "# CARMEN Logfile
+# file format is one message per line
+# message_name [message contents] ipc_timestamp ipc_hostname logger_timestamp
+# message formats defined: ODOM TRUEPOS FLASER RLASER LASER3 LASER4 ROBOTLASER1
+# ODOM x y theta tv rv accel
+# TRUEPOS true_x true_y true_theta odom_x odom_y odom_theta
+# FLASER num_readings [range_readings] x y theta odom_x odom_y odom_theta
+# ROBOTLASER1 laser_type start_angle field_of_view angular_resolution maximum_range accuracy \
+remission_mode num_readings [range_readings] num_remissions [remission values] laser_pose_x \
+laser_pose_y laser_pose_theta robot_pose_x robot_pose_y robot_pose_theta laser_tv laser_rv \
+forward_safety_dist side_safty_dist turn_axis
+";
+
+// CARMEN's laser type for a simulated laser
+const SIMULATED_LASER: u32 = 3;
+
+/// Names of the `FLASER` style messages, for the first four sensors.
+const LASER_MESSAGES: [&str; 4] = ["FLASER", "RLASER", "LASER3", "LASER4"];
+
+/// Message used for the scans in CARMEN logs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CarmenLaser {
+    /// `ROBOTLASER1`, which carries the beam layout of each scan.
+    RobotLaser,
+    /// The older `FLASER` (and `RLASER`, `LASER3`, `LASER4` for further sensors), which readers
+    /// assume to be a 180 degree scan.
+    FLaser,
+}
+
+impl FromStr for CarmenLaser {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "robotlaser" => Ok(CarmenLaser::RobotLaser),
+            "flaser" => Ok(CarmenLaser::FLaser),
+            _ => Err(format!(
+                "Unknown CARMEN laser message '{}', expected one of robotlaser, flaser",
+                s
+            )),
+        }
+    }
+}
+
+/// Exports each robot's run as a CARMEN log, with `ODOM` lines from the simulated odometry,
+/// `TRUEPOS` lines with the ground truth, and a laser line per scan.
+///
+/// Like the MCAP export, the world is mirrored into a right-handed frame in metres, and the
+/// beams of each scan are reversed so the angles increase.
+pub struct CarmenExporter {
+    /// Scale from the simulation's pixels to metres.
+    pub metres_per_pixel: f64,
+    pub laser: CarmenLaser,
+}
+
+impl CarmenExporter {
+    pub fn new(metres_per_pixel: f64, laser: CarmenLaser) -> Self {
+        Self {
+            metres_per_pixel,
+            laser,
+        }
+    }
+
+    /// Ranges in metres in increasing angle order, with beams that hit nothing, or were
+    /// invalid, at the maximum range.
+    fn ranges(&self, beams: &BeamModel, returns: &Array2<f64>) -> Vec<f64> {
+        let max_range = beams.max_range as f64;
+        returns
+            .column(0)
+            .iter()
+            .rev()
+            .map(|r| {
+                if r.is_finite() && *r < max_range - 1E-3 {
+                    r * self.metres_per_pixel
+                } else {
+                    max_range * self.metres_per_pixel
+                }
+            })
+            .collect()
+    }
+
+    fn write_log(&self, out: &mut impl Write, robot: &RobotRecord) -> IoResult<()> {
+        out.write_all(LOG_HEADER.as_bytes())?;
+
+        let mut previous: Option<(f64, MetricPose)> = None;
+        for (i, t) in robot.times.iter().enumerate() {
+            let truth = MetricPose::from_pose(robot.poses[i], self.metres_per_pixel);
+            let odom = MetricPose::from_pose(robot.odometry[i], self.metres_per_pixel);
+            let (tv, _, rv) = match previous {
+                Some((t0, p0)) => odom.velocity_from(p0, t - t0),
+                None => (0.0, 0.0, 0.0),
+            };
+            previous = Some((*t, odom));
+
+            writeln!(
+                out,
+                "ODOM {:.6} {:.6} {:.6} {:.6} {:.6} 0.000000 {:.6} {} {:.6}",
+                odom.x, odom.y, odom.yaw, tv, rv, t, HOSTNAME, t
+            )?;
+            writeln!(
+                out,
+                "TRUEPOS {:.6} {:.6} {:.6} {:.6} {:.6} {:.6} {:.6} {} {:.6}",
+                truth.x, truth.y, truth.yaw, odom.x, odom.y, odom.yaw, t, HOSTNAME, t
+            )?;
+
+            for (k, sensor) in robot.sensors.iter().enumerate() {
+                let beams = &sensor.config.beams;
+                let ranges = self.ranges(beams, &sensor.returns[i]);
+                let ranges: Vec<String> = ranges.iter().map(|r| format!("{:.3}", r)).collect();
+
+                // The laser pose is given in the odometry frame
+                let laser = MetricPose::from_pose(
+                    robot.odometry[i].compose(sensor.config.mount.as_pose()),
+                    self.metres_per_pixel,
+                );
+
+                match self.laser {
+                    CarmenLaser::RobotLaser => {
+                        let angle_increment = beams.angle_increment() as f64;
+                        let fov = beams.n_beams.saturating_sub(1) as f64 * angle_increment;
+                        let start_angle = -(beams.angle_min() as f64 + fov);
+                        writeln!(
+                            out,
+                            "ROBOTLASER1 {} {:.6} {:.6} {:.6} {:.3} 0.01 0 {} {} 0 \
+                             {:.6} {:.6} {:.6} {:.6} {:.6} {:.6} {:.6} {:.6} 0 0 0 {:.6} {} {:.6}",
+                            SIMULATED_LASER,
+                            start_angle,
+                            fov,
+                            angle_increment,
+                            beams.max_range as f64 * self.metres_per_pixel,
+                            ranges.len(),
+                            ranges.join(" "),
+                            laser.x,
+                            laser.y,
+                            laser.yaw,
+                            odom.x,
+                            odom.y,
+                            odom.yaw,
+                            tv,
+                            rv,
+                            t,
+                            HOSTNAME,
+                            t
+                        )?;
+                    }
+                    CarmenLaser::FLaser => {
+                        // There are only messages for four lasers
+                        let Some(message) = LASER_MESSAGES.get(k) else {
+                            continue;
+                        };
+                        writeln!(
+                            out,
+                            "{} {} {} {:.6} {:.6} {:.6} {:.6} {:.6} {:.6} {:.6} {} {:.6}",
+                            message,
+                            ranges.len(),
+                            ranges.join(" "),
+                            laser.x,
+                            laser.y,
+                            laser.yaw,
+                            odom.x,
+                            odom.y,
+                            odom.yaw,
+                            t,
+                            HOSTNAME,
+                            t
+                        )?;
+                    }
+                }
+            }
+        }
+
+        out.flush()
+    }
+}
+
+impl Exporter for CarmenExporter {
+    fn export(&self, run: &RunRecord) -> IoResult<Vec<String>> {
+        let mut filenames = Vec::new();
+        for (r, robot) in run.robots.iter().enumerate() {
+            let filename = run.robot_filename("carmen", r, "log");
+            let file = File::create(&filename)?;
+            self.write_log(&mut BufWriter::new(file), robot)?;
+            filenames.push(filename);
+        }
+        Ok(filenames)
+    }
+}
@@ -0,0 +1,106 @@
+/// A little-endian CDR (XCDR1) encoder, for serializing ROS 2 messages.
+pub struct CdrWriter {
+    buf: Vec<u8>,
+}
+
+impl CdrWriter {
+    /// Starts a message with the little-endian CDR encapsulation header.
+    pub fn new() -> Self {
+        Self {
+            buf: vec![0x00, 0x01, 0x00, 0x00],
+        }
+    }
+
+    /// Pads to a multiple of `n` bytes, counted from the end of the encapsulation header.
+    fn align(&mut self, n: usize) {
+        while !(self.buf.len() - 4).is_multiple_of(n) {
+            self.buf.push(0);
+        }
+    }
+
+    pub fn i8(&mut self, v: i8) {
+        self.buf.push(v as u8);
+    }
+
+    pub fn u32(&mut self, v: u32) {
+        self.align(4);
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub fn i32(&mut self, v: i32) {
+        self.align(4);
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub fn f32(&mut self, v: f32) {
+        self.align(4);
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub fn f64(&mut self, v: f64) {
+        self.align(8);
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    /// A string, as its length including the null terminator followed by the bytes.
+    pub fn string(&mut self, s: &str) {
+        self.u32(s.len() as u32 + 1);
+        self.buf.extend_from_slice(s.as_bytes());
+        self.buf.push(0);
+    }
+
+    /// The length prefix of an unbounded sequence.
+    pub fn sequence_length(&mut self, n: usize) {
+        self.u32(n as u32);
+    }
+
+    pub fn f32_sequence(&mut self, values: &[f32]) {
+        self.sequence_length(values.len());
+        for v in values.iter() {
+            self.f32(*v);
+        }
+    }
+
+    pub fn i8_sequence(&mut self, values: &[i8]) {
+        self.sequence_length(values.len());
+        for v in values.iter() {
+            self.i8(*v);
+        }
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_are_aligned_after_the_header() {
+        let mut cdr = CdrWriter::new();
+        cdr.i8(1);
+        cdr.u32(2);
+        cdr.i8(3);
+        cdr.f64(4.0);
+        let bytes = cdr.into_bytes();
+
+        assert_eq!(&bytes[..4], &[0, 1, 0, 0]);
+        assert_eq!(&bytes[4..12], &[1, 0, 0, 0, 2, 0, 0, 0]);
+        assert_eq!(&bytes[12..20], &[3, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(&bytes[20..], &4.0_f64.to_le_bytes());
+    }
+
+    #[test]
+    fn strings_and_sequences_are_length_prefixed() {
+        let mut cdr = CdrWriter::new();
+        cdr.string("map");
+        cdr.f32_sequence(&[1.5]);
+        let bytes = cdr.into_bytes();
+
+        assert_eq!(&bytes[4..12], &[4, 0, 0, 0, b'm', b'a', b'p', 0]);
+        assert_eq!(&bytes[12..16], &[1, 0, 0, 0]);
+        assert_eq!(&bytes[16..], &1.5_f32.to_le_bytes());
+    }
+}
@@ -0,0 +1,222 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Result as IoResult;
+
+use crate::lidar::argument_parser::{find_option, OPTIONS};
+use serde_json::Value;
+
+/// Reads a config file into option values, as they would be given on the command line.
+///
+/// The file is JSON if its name ends in `.json`, and TOML otherwise. It has a table for each
+/// section of the options listed by `--help`, holding any of that section's options, with flags
+/// as booleans, lists either as strings or as arrays, and randomized values as strings or tables.
+pub fn load(path: &str) -> Result<BTreeMap<String, String>, String> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| format!("Could not read the config file {}: {}", path, e))?;
+    let root: Value = if path.ends_with(".json") {
+        serde_json::from_str(&text).map_err(|e| format!("Invalid config file {}: {}", path, e))?
+    } else {
+        toml::from_str(&text).map_err(|e| format!("Invalid config file {}: {}", path, e))?
+    };
+
+    let Value::Object(sections) = root else {
+        return Err(format!("Invalid config file {}: expected sections", path));
+    };
+
+    let mut values = BTreeMap::new();
+    for (section, options) in sections.iter() {
+        let Value::Object(options) = options else {
+            return Err(format!(
+                "Invalid config file {}: [{}] is not a section",
+                path, section
+            ));
+        };
+
+        for (name, value) in options.iter() {
+            let Some(spec) = find_option(name).filter(|o| o.section == section) else {
+                return Err(format!("Unknown option {}.{} in {}", section, name, path));
+            };
+
+            match (spec.value, value) {
+                (None, Value::Bool(true)) => {
+                    values.insert(name.clone(), String::new());
+                }
+                (None, Value::Bool(false)) => {}
+                (None, _) => {
+                    return Err(format!(
+                        "{}.{} in {} must be true or false",
+                        section, name, path
+                    ))
+                }
+                (Some(_), value) => {
+                    let value = option_value(name, value)
+                        .ok_or_else(|| format!("Invalid {}.{} in {}", section, name, path))?;
+                    values.insert(name.clone(), value);
+                }
+            }
+        }
+    }
+    Ok(values)
+}
+
+/// A config value as it would be given on the command line. Arrays are joined with the list
+/// separator of the option, and nested arrays, such as the vertices of the robot body, with
+/// commas. Tables of `min` and `max` are ranges, and tables of a `choice` array sets of choices,
+/// both drawn on every iteration.
+fn option_value(name: &str, value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Object(table) => match (table.get("min"), table.get("max"), table.get("choice")) {
+            (Some(Value::Number(min)), Some(Value::Number(max)), None) if table.len() == 2 => {
+                Some(format!("{}..{}", min, max))
+            }
+            (None, None, Some(Value::Array(choices))) if table.len() == 1 => {
+                let choices = choices
+                    .iter()
+                    .map(|choice| match choice {
+                        Value::String(_) | Value::Number(_) => option_value(name, choice),
+                        _ => None,
+                    })
+                    .collect::<Option<Vec<String>>>()?;
+                Some(choices.join("|"))
+            }
+            _ => None,
+        },
+        Value::Array(items) => {
+            let separator = match name {
+                "sensors" | "robot_body" => ";",
+                _ => ",",
+            };
+            let items = items
+                .iter()
+                .map(|item| match item {
+                    Value::Array(_) => option_value("", item),
+                    _ => option_value(name, item),
+                })
+                .collect::<Option<Vec<String>>>()?;
+            Some(items.join(separator))
+        }
+        _ => None,
+    }
+}
+
+/// Writes every setting to `config.toml` in the output directory, in the layout read by
+/// `--config`, so that the run can be repeated from it. Returns the name of the file.
+pub fn write(out_dir: &str, resolved: &BTreeMap<String, String>) -> IoResult<String> {
+    let filename = format!("{}/config.toml", out_dir);
+    let text = toml::to_string(&sections(resolved)).map_err(std::io::Error::other)?;
+    fs::write(&filename, text)?;
+    Ok(filename)
+}
+
+/// Every setting, grouped by section in the layout read by `--config`.
+pub fn sections(resolved: &BTreeMap<String, String>) -> toml::Table {
+    let mut sections = toml::Table::new();
+    for spec in OPTIONS.iter().filter(|o| !o.section.is_empty()) {
+        let value = match (spec.value, resolved.get(spec.name)) {
+            (None, given) => toml::Value::Boolean(given.is_some()),
+            (Some(_), Some(value)) => toml_value(value),
+            (Some(_), None) => continue,
+        };
+
+        if let toml::Value::Table(section) = sections
+            .entry(spec.section)
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+        {
+            section.insert(spec.name.to_string(), value);
+        }
+    }
+    sections
+}
+
+/// A setting as a TOML number where it is one, and a string otherwise. Integers beyond TOML's
+/// range, such as most seeds, are strings too, as a float would lose their value.
+fn toml_value(value: &str) -> toml::Value {
+    if let Ok(i) = value.parse::<i64>() {
+        return toml::Value::Integer(i);
+    }
+    if value.parse::<u64>().is_ok() {
+        return toml::Value::String(value.to_string());
+    }
+    let numeric = value
+        .chars()
+        .all(|c| c.is_ascii_digit() || "+-.eE".contains(c));
+    match value.parse::<f64>() {
+        Ok(f) if numeric => toml::Value::Float(f),
+        _ => toml::Value::String(value.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("lidar_config_{}", name));
+        fs::create_dir_all(&dir).unwrap();
+        dir.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn written_settings_load_unchanged() {
+        let resolved: BTreeMap<String, String> = [
+            ("seed", "18446744073709551615"),
+            ("n_iterations", "5"),
+            ("metres_per_pixel", "0.01"),
+            ("planner", "rrt_star"),
+            ("odometry_noise", "0.05,0.0005,0.05,1.0"),
+            ("sensors", "360|720,6.28,1000,0,0,0;90,3.14,1000,1,0,3.14"),
+            ("n_shapes", "20..150"),
+            ("labels", ""),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+
+        let filename = write(&temp_dir("round_trip"), &resolved).unwrap();
+        assert_eq!(load(&filename).unwrap(), resolved);
+    }
+
+    #[test]
+    fn loads_arrays_and_randomized_tables() {
+        let dir = temp_dir("tables");
+        let filename = format!("{}/config.toml", dir);
+        fs::write(
+            &filename,
+            "[motion]\nrobot_body = [[20, -15], [20, 15], [-20, 15]]\n\
+             [geometry]\nn_shapes = { min = 20, max = 150 }\n\
+             [planner]\nplanner = { choice = [\"astar\", \"prm\"] }\n\
+             [output]\nexport = [\"tum\", \"g2o\"]\nlabels = true\nnormals = false\n",
+        )
+        .unwrap();
+
+        let values = load(&filename).unwrap();
+        assert_eq!(values["robot_body"], "20,-15;20,15;-20,15");
+        assert_eq!(values["n_shapes"], "20..150");
+        assert_eq!(values["planner"], "astar|prm");
+        assert_eq!(values["export"], "tum,g2o");
+        assert_eq!(values["labels"], "");
+        assert!(!values.contains_key("normals"));
+    }
+
+    #[test]
+    fn rejects_unknown_and_misplaced_options() {
+        let dir = temp_dir("errors");
+        let filename = format!("{}/config.json", dir);
+        for (text, error) in [
+            (
+                r#"{"planner": {"bogus": 1}}"#,
+                "Unknown option planner.bogus",
+            ),
+            (
+                r#"{"motion": {"planner": "prm"}}"#,
+                "Unknown option motion.planner",
+            ),
+            (r#"{"output": {"labels": 1}}"#, "must be true or false"),
+        ] {
+            fs::write(&filename, text).unwrap();
+            assert!(load(&filename).unwrap_err().contains(error), "{}", text);
+        }
+    }
+}
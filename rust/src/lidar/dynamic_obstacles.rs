@@ -0,0 +1,398 @@
+use std::f32::consts::{PI, TAU};
+use std::str::FromStr;
+
+use crate::lidar::geometry::{
+    point_in_polygon, polygon_edge_normal, ray_polygon_distance, segment_is_free,
+};
+use crate::lidar::motion::{wrap_angle, Pose2D};
+use crate::lidar::rng;
+use crate::lidar::serializer::SerializableArray2;
+use godot::prelude::*;
+use ndarray::Array2;
+use serde::Serialize;
+
+/// How a dynamic obstacle moves around the arena.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MotionPattern {
+    /// Straight lines, bouncing off the arena edges and the static geometry.
+    LinearBounce,
+    /// Cycling through a set of random waypoints with clear lines of sight between them.
+    Waypoints,
+    /// A random walk, with the heading drifting and turning round at obstacles.
+    RandomWalk,
+}
+
+impl FromStr for MotionPattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bounce" => Ok(MotionPattern::LinearBounce),
+            "waypoints" => Ok(MotionPattern::Waypoints),
+            "random_walk" => Ok(MotionPattern::RandomWalk),
+            _ => Err(format!(
+                "Unknown dynamic obstacle pattern '{}', expected one of bounce, waypoints, random_walk",
+                s
+            )),
+        }
+    }
+}
+
+impl MotionPattern {
+    fn name(&self) -> &'static str {
+        match self {
+            MotionPattern::LinearBounce => "bounce",
+            MotionPattern::Waypoints => "waypoints",
+            MotionPattern::RandomWalk => "random_walk",
+        }
+    }
+}
+
+/// Parses a comma separated list of motion patterns.
+pub fn parse_pattern_list(s: &str) -> Result<Vec<MotionPattern>, String> {
+    s.split(',')
+        .filter(|p| !p.trim().is_empty())
+        .map(|p| p.trim().parse())
+        .collect()
+}
+
+/// Shape of a dynamic obstacle, centred on its position.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ObstacleShape {
+    /// A pedestrian.
+    Circle { radius: f32 },
+    /// A cart, with its length along the heading.
+    Rectangle { length: f32, width: f32 },
+}
+
+impl ObstacleShape {
+    /// Outline in the world frame at `pose`, with circles approximated by a 16-gon for drawing.
+    pub fn world_polygon(&self, pose: Pose2D) -> Vec<Vector2> {
+        let local: Vec<Vector2> = match *self {
+            ObstacleShape::Circle { radius } => (0..16)
+                .map(|k| Vector2::from_angle(k as f32 * TAU / 16.0) * radius)
+                .collect(),
+            ObstacleShape::Rectangle { length, width } => {
+                let (l, w) = (length / 2.0, width / 2.0);
+                vec![
+                    Vector2::new(l, -w),
+                    Vector2::new(l, w),
+                    Vector2::new(-l, w),
+                    Vector2::new(-l, -w),
+                ]
+            }
+        };
+        local
+            .iter()
+            .map(|v| pose.position + v.rotated(pose.heading))
+            .collect()
+    }
+
+    /// Distance along a ray from `origin` in the unit `direction` to the shape at `pose`, with the
+    /// surface normal at the hit.
+    pub fn ray_hit(
+        &self,
+        pose: Pose2D,
+        origin: Vector2,
+        direction: Vector2,
+    ) -> Option<(f32, Vector2)> {
+        match *self {
+            ObstacleShape::Circle { radius } => {
+                // Nearest root of |origin + t * direction - centre| = radius
+                let to_origin = origin - pose.position;
+                let b = to_origin.dot(direction);
+                let c = to_origin.length_squared() - radius * radius;
+                let discriminant = b * b - c;
+                if discriminant < 0.0 {
+                    return None;
+                }
+                let root = discriminant.sqrt();
+                let t = [-b - root, -b + root].into_iter().find(|t| *t > 1E-3)?;
+
+                // Facing the ray, also when it starts inside the circle
+                let normal = (origin + direction * t - pose.position).normalized();
+                Some((
+                    t,
+                    if normal.dot(direction) > 0.0 {
+                        -normal
+                    } else {
+                        normal
+                    },
+                ))
+            }
+            ObstacleShape::Rectangle { .. } => {
+                let polygon = self.world_polygon(pose);
+                ray_polygon_distance(origin, direction, &polygon)
+                    .map(|(d, edge)| (d, polygon_edge_normal(&polygon, edge, direction)))
+            }
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            ObstacleShape::Circle { .. } => "circle",
+            ObstacleShape::Rectangle { .. } => "rectangle",
+        }
+    }
+
+    fn size(&self) -> [f32; 2] {
+        match *self {
+            ObstacleShape::Circle { radius } => [2.0 * radius, 2.0 * radius],
+            ObstacleShape::Rectangle { length, width } => [length, width],
+        }
+    }
+}
+
+/// A moving obstacle, hit by the rays but not part of the static geometry.
+pub struct DynamicObstacle {
+    pub shape: ObstacleShape,
+    pub pattern: MotionPattern,
+    pub pose: Pose2D,
+    /// Speed along the heading (px/s).
+    pub speed: f32,
+    /// Largest change in heading of a random walk (rad/s).
+    pub turn_rate: f32,
+    waypoints: Vec<Vector2>,
+    waypoint_idx: usize,
+    track: Vec<(f64, Pose2D)>,
+}
+
+impl DynamicObstacle {
+    pub fn new(shape: ObstacleShape, pattern: MotionPattern, pose: Pose2D, speed: f32) -> Self {
+        Self {
+            shape,
+            pattern,
+            pose,
+            speed,
+            turn_rate: 2.0,
+            waypoints: Vec::new(),
+            waypoint_idx: 0,
+            track: Vec::new(),
+        }
+    }
+
+    /// Creates an obstacle at a random free position in the arena, with a random heading and,
+    /// for the waypoints pattern, up to `n_waypoints` random waypoints to cycle through.
+    pub fn random(
+        shape: ObstacleShape,
+        pattern: MotionPattern,
+        speed: f32,
+        arena: &Arena,
+        n_waypoints: usize,
+    ) -> Option<Self> {
+        let start = (0..1000)
+            .map(|_| arena.sample_point())
+            .find(|p| arena.is_free(*p))?;
+        let heading = rng::random::<f32>() * TAU - PI;
+        let mut obstacle = Self::new(shape, pattern, Pose2D::new(start, heading), speed);
+
+        if pattern == MotionPattern::Waypoints {
+            let mut waypoints = vec![start];
+            for _ in 0..1000 {
+                if waypoints.len() >= n_waypoints.max(2) {
+                    break;
+                }
+                let candidate = arena.sample_point();
+                let last = *waypoints.last().unwrap();
+                if arena.is_free(candidate)
+                    && segment_is_free(last, candidate, &arena.obstacles, arena.clearance)
+                {
+                    waypoints.push(candidate);
+                }
+            }
+
+            // The tour returns from the last waypoint to the start
+            while waypoints.len() > 2 {
+                let last = *waypoints.last().unwrap();
+                if segment_is_free(last, start, &arena.obstacles, arena.clearance) {
+                    break;
+                }
+                waypoints.pop();
+            }
+            obstacle.waypoints = waypoints;
+            obstacle.waypoint_idx = 1 % obstacle.waypoints.len();
+        }
+
+        Some(obstacle)
+    }
+
+    /// Moves the obstacle `dt` seconds along its pattern, recording its pose at `time`.
+    pub fn step(&mut self, dt: f32, time: f64, arena: &Arena) {
+        match self.pattern {
+            MotionPattern::LinearBounce => {
+                let velocity = Vector2::from_angle(self.pose.heading) * self.speed;
+                let next = self.pose.position + velocity * dt;
+
+                // Reflect off the arena edges, and reverse off the static geometry
+                if next.x < arena.min.x || next.x > arena.max.x {
+                    self.pose.heading = wrap_angle(PI - self.pose.heading);
+                } else if next.y < arena.min.y || next.y > arena.max.y {
+                    self.pose.heading = wrap_angle(-self.pose.heading);
+                } else if !arena.is_free(next) {
+                    self.pose.heading = wrap_angle(self.pose.heading + PI);
+                } else {
+                    self.pose.position = next;
+                }
+            }
+            MotionPattern::Waypoints => {
+                if self.waypoints.len() > 1 {
+                    let target = self.waypoints[self.waypoint_idx];
+                    let offset = target - self.pose.position;
+                    let step = self.speed * dt;
+                    if offset.length() <= step {
+                        self.pose.position = target;
+                        self.waypoint_idx = (self.waypoint_idx + 1) % self.waypoints.len();
+                    } else {
+                        self.pose.heading = offset.angle();
+                        self.pose.position += offset.normalized() * step;
+                    }
+                }
+            }
+            MotionPattern::RandomWalk => {
+                let turn = (rng::random::<f32>() * 2.0 - 1.0) * self.turn_rate * dt;
+                self.pose.heading = wrap_angle(self.pose.heading + turn);
+
+                let next =
+                    self.pose.position + Vector2::from_angle(self.pose.heading) * self.speed * dt;
+                if arena.contains(next) && arena.is_free(next) {
+                    self.pose.position = next;
+                } else {
+                    self.pose.heading = wrap_angle(self.pose.heading + PI);
+                }
+            }
+        }
+
+        self.track.push((time, self.pose));
+    }
+
+    /// The ground-truth track of the obstacle, for serialization.
+    pub fn track(&self, id: usize) -> ObstacleTrack {
+        let track = Array2::from_shape_vec(
+            (self.track.len(), 4),
+            self.track
+                .iter()
+                .flat_map(|(t, p)| {
+                    vec![
+                        *t,
+                        p.position.x as f64,
+                        p.position.y as f64,
+                        p.heading as f64,
+                    ]
+                })
+                .collect(),
+        )
+        .unwrap();
+
+        ObstacleTrack {
+            id,
+            shape: self.shape.name(),
+            pattern: self.pattern.name(),
+            size: self.shape.size(),
+            track: SerializableArray2 { array: track },
+        }
+    }
+}
+
+/// Ground-truth track of a dynamic obstacle: one row of time, x, y, heading per step.
+#[derive(Serialize)]
+pub struct ObstacleTrack {
+    pub id: usize,
+    pub shape: &'static str,
+    pub pattern: &'static str,
+    pub size: [f32; 2],
+    pub track: SerializableArray2<f64>,
+}
+
+/// The bounds and static geometry the dynamic obstacles move around in.
+pub struct Arena {
+    pub min: Vector2,
+    pub max: Vector2,
+    pub obstacles: Vec<Vec<Vector2>>,
+    /// Distance kept from the static geometry between waypoints (px).
+    pub clearance: f32,
+}
+
+impl Arena {
+    fn sample_point(&self) -> Vector2 {
+        Vector2::new(
+            self.min.x + rng::random::<f32>() * (self.max.x - self.min.x),
+            self.min.y + rng::random::<f32>() * (self.max.y - self.min.y),
+        )
+    }
+
+    fn contains(&self, point: Vector2) -> bool {
+        point.x >= self.min.x
+            && point.x <= self.max.x
+            && point.y >= self.min.y
+            && point.y <= self.max.y
+    }
+
+    fn is_free(&self, point: Vector2) -> bool {
+        !self
+            .obstacles
+            .iter()
+            .any(|polygon| point_in_polygon(point, polygon))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 200 px square arena with a block in the middle.
+    fn arena() -> Arena {
+        Arena {
+            min: Vector2::new(0.0, 0.0),
+            max: Vector2::new(200.0, 200.0),
+            obstacles: vec![vec![
+                Vector2::new(80.0, 80.0),
+                Vector2::new(120.0, 80.0),
+                Vector2::new(120.0, 120.0),
+                Vector2::new(80.0, 120.0),
+            ]],
+            clearance: 5.0,
+        }
+    }
+
+    #[test]
+    fn obstacles_stay_in_the_free_space() {
+        let arena = arena();
+        let shape = ObstacleShape::Circle { radius: 5.0 };
+        for pattern in [
+            MotionPattern::LinearBounce,
+            MotionPattern::Waypoints,
+            MotionPattern::RandomWalk,
+        ] {
+            for _ in 0..10 {
+                let mut obstacle =
+                    DynamicObstacle::random(shape, pattern, 50.0, &arena, 4).unwrap();
+                for k in 0..500 {
+                    obstacle.step(0.1, k as f64 * 0.1, &arena);
+                    let position = obstacle.pose.position;
+                    assert!(
+                        arena.contains(position),
+                        "{:?} left at {:?}",
+                        pattern,
+                        position
+                    );
+                    assert!(
+                        arena.is_free(position),
+                        "{:?} entered at {:?}",
+                        pattern,
+                        position
+                    );
+                }
+                assert_eq!(obstacle.track(0).track.array.nrows(), 500);
+            }
+        }
+    }
+
+    #[test]
+    fn patterns_are_parsed() {
+        assert_eq!(
+            parse_pattern_list("bounce, random_walk").unwrap(),
+            [MotionPattern::LinearBounce, MotionPattern::RandomWalk]
+        );
+        assert!(parse_pattern_list("bounce,orbit").is_err());
+    }
+}
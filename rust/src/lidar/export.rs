@@ -0,0 +1,206 @@
+use std::io::Result as IoResult;
+use std::str::FromStr;
+
+use crate::lidar::carmen_export::{CarmenExporter, CarmenLaser};
+use crate::lidar::g2o_export::G2oExporter;
+use crate::lidar::mcap_export::McapExporter;
+use crate::lidar::motion::Pose2D;
+use crate::lidar::npz_export::NpzExporter;
+use crate::lidar::occupancy_grid::OccupancyGrid;
+use crate::lidar::odometry::OdometryNoise;
+use crate::lidar::parquet_export::ParquetExporter;
+use crate::lidar::pose_export::{KittiExporter, TumExporter};
+use crate::lidar::sensor::SensorConfig;
+use crate::lidar::trajectory::LoopClosure;
+use godot::prelude::*;
+use ndarray::Array2;
+
+/// An additional output format for the recorded runs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExportFormat {
+    /// A ROS 2 bag in MCAP format.
+    Mcap,
+    /// CARMEN logs, for the classic 2D SLAM tools.
+    Carmen,
+    /// NumPy `.npz` archives of the scans and trajectories.
+    Npz,
+    /// A Parquet dataset of the scans of every iteration.
+    Parquet,
+    /// TUM trajectories of the ground truth and odometry, for `evo` and similar tools.
+    Tum,
+    /// KITTI odometry trajectories of the ground truth and odometry.
+    Kitti,
+    /// g2o pose graphs of the odometry and ground-truth loop closures.
+    G2o,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mcap" => Ok(ExportFormat::Mcap),
+            "carmen" => Ok(ExportFormat::Carmen),
+            "npz" => Ok(ExportFormat::Npz),
+            "parquet" => Ok(ExportFormat::Parquet),
+            "tum" => Ok(ExportFormat::Tum),
+            "kitti" => Ok(ExportFormat::Kitti),
+            "g2o" => Ok(ExportFormat::G2o),
+            _ => Err(format!(
+                "Unknown export format '{}', expected one of mcap, carmen, npz, parquet, tum, \
+                 kitti, g2o",
+                s
+            )),
+        }
+    }
+}
+
+/// Parses a comma separated list of export formats.
+pub fn parse_format_list(s: &str) -> Result<Vec<ExportFormat>, String> {
+    s.split(',')
+        .filter(|f| !f.trim().is_empty())
+        .map(|f| f.trim().parse())
+        .collect()
+}
+
+/// Settings shared by the exporters.
+#[derive(Clone, Copy, Debug)]
+pub struct ExportOptions {
+    /// Scale from the simulation's pixels to metres.
+    pub metres_per_pixel: f64,
+    /// Message used for the scans in CARMEN logs.
+    pub carmen_laser: CarmenLaser,
+}
+
+impl ExportOptions {
+    pub fn new() -> Self {
+        Self {
+            metres_per_pixel: 0.01,
+            carmen_laser: CarmenLaser::RobotLaser,
+        }
+    }
+}
+
+/// Creates the exporter for a format.
+pub fn create_exporter(format: ExportFormat, options: &ExportOptions) -> Box<dyn Exporter> {
+    match format {
+        ExportFormat::Mcap => Box::new(McapExporter::new(options.metres_per_pixel)),
+        ExportFormat::Carmen => Box::new(CarmenExporter::new(
+            options.metres_per_pixel,
+            options.carmen_laser,
+        )),
+        ExportFormat::Npz => Box::new(NpzExporter::new()),
+        ExportFormat::Parquet => Box::new(ParquetExporter::new()),
+        ExportFormat::Tum => Box::new(TumExporter::new(options.metres_per_pixel)),
+        ExportFormat::Kitti => Box::new(KittiExporter::new(options.metres_per_pixel)),
+        ExportFormat::G2o => Box::new(G2oExporter::new(options.metres_per_pixel)),
+    }
+}
+
+/// A planar pose in metres in a right-handed frame, as used by ROS and most SLAM tools.
+///
+/// Godot's y axis points down, so the world is mirrored: y and the heading change sign.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MetricPose {
+    pub x: f64,
+    pub y: f64,
+    pub yaw: f64,
+}
+
+impl MetricPose {
+    pub fn from_pose(pose: Pose2D, metres_per_pixel: f64) -> Self {
+        Self {
+            x: pose.position.x as f64 * metres_per_pixel,
+            y: -pose.position.y as f64 * metres_per_pixel,
+            yaw: -pose.heading as f64,
+        }
+    }
+
+    /// Forward, sideways and angular velocity in the frame of this pose, moving from `previous`
+    /// over `dt` seconds.
+    pub fn velocity_from(&self, previous: MetricPose, dt: f64) -> (f64, f64, f64) {
+        if dt <= 0.0 {
+            return (0.0, 0.0, 0.0);
+        }
+
+        let (dx, dy) = ((self.x - previous.x) / dt, (self.y - previous.y) / dt);
+        let (sin, cos) = self.yaw.sin_cos();
+        let dyaw = (self.yaw - previous.yaw + std::f64::consts::PI)
+            .rem_euclid(std::f64::consts::TAU)
+            - std::f64::consts::PI;
+        (cos * dx + sin * dy, -sin * dx + cos * dy, dyaw / dt)
+    }
+
+    /// This pose in the frame of `origin`.
+    pub fn relative_to(&self, origin: MetricPose) -> MetricPose {
+        let (dx, dy) = (self.x - origin.x, self.y - origin.y);
+        let (sin, cos) = origin.yaw.sin_cos();
+        MetricPose {
+            x: cos * dx + sin * dy,
+            y: -sin * dx + cos * dy,
+            yaw: (self.yaw - origin.yaw + std::f64::consts::PI).rem_euclid(std::f64::consts::TAU)
+                - std::f64::consts::PI,
+        }
+    }
+}
+
+/// Writes a recorded iteration in some output format.
+pub trait Exporter {
+    /// Writes the iteration, returning the names of the files written.
+    fn export(&self, run: &RunRecord) -> IoResult<Vec<String>>;
+}
+
+/// The scans of one sensor over an iteration.
+pub struct SensorRecord<'a> {
+    pub config: SensorConfig,
+    /// One array per scan with a row of range, bearing (and any extra columns) per beam.
+    pub returns: &'a [Array2<f64>],
+    /// One array per scan with a row of object id, class and dynamic flag per beam, empty
+    /// without labels.
+    pub labels: &'a [Array2<i64>],
+    /// World pose of the sensor at each scan.
+    pub poses: &'a [Pose2D],
+}
+
+/// Everything recorded by one robot over an iteration.
+pub struct RobotRecord<'a> {
+    /// Time of each scan (s).
+    pub times: &'a [f64],
+    /// Ground-truth pose of the robot base at each scan.
+    pub poses: &'a [Pose2D],
+    /// Simulated odometry pose of the robot base at each scan.
+    pub odometry: &'a [Pose2D],
+    /// The planned path.
+    pub path: &'a [Vector2],
+    /// Ground-truth loop closures between the scans.
+    pub loop_closures: Vec<LoopClosure>,
+    pub sensors: Vec<SensorRecord<'a>>,
+}
+
+/// A completed iteration, as passed to the exporters.
+pub struct RunRecord<'a> {
+    pub out_dir: &'a str,
+    pub iteration: u32,
+    pub robots: Vec<RobotRecord<'a>>,
+    /// The static geometry, rasterized.
+    pub map: &'a OccupancyGrid,
+    /// Noise the odometry was simulated with.
+    pub odometry_noise: OdometryNoise,
+    /// Columns of each row of the returns, five with the normals and two otherwise.
+    pub return_columns: usize,
+}
+
+impl RunRecord<'_> {
+    /// Output filename for one robot, with a `_robotR` suffix when there is more than one.
+    pub fn robot_filename(&self, kind: &str, r: usize, extension: &str) -> String {
+        let suffix = if self.robots.len() > 1 {
+            format!("_robot{}", r)
+        } else {
+            String::new()
+        };
+        format!(
+            "{}/lidar_{}_{}{}.{}",
+            self.out_dir, kind, self.iteration, suffix, extension
+        )
+    }
+}
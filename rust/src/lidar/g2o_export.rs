@@ -0,0 +1,207 @@
+use std::fs::File;
+use std::io::{BufWriter, Result as IoResult, Write};
+
+use crate::lidar::export::{Exporter, MetricPose, RobotRecord, RunRecord};
+use crate::lidar::motion::Pose2D;
+use crate::lidar::odometry::OdometryNoise;
+
+/// Standard deviations added to every odometry edge, so that edges without motion, which have
+/// no odometry noise, still have an invertible covariance (m, rad).
+const MIN_TRANSLATION_SIGMA: f64 = 1E-3;
+const MIN_ROTATION_SIGMA: f64 = 1E-3;
+
+/// Standard deviations of the loop closure edges, as for a good scan matcher (m, rad).
+const LOOP_CLOSURE_TRANSLATION_SIGMA: f64 = 0.05;
+const LOOP_CLOSURE_ROTATION_SIGMA: f64 = 0.01;
+
+/// Inverse of a symmetric positive definite 3x3 matrix.
+fn invert(m: [[f64; 3]; 3]) -> [[f64; 3]; 3] {
+    let cofactor = |i: usize, j: usize| {
+        let (r0, r1) = ((i + 1) % 3, (i + 2) % 3);
+        let (c0, c1) = ((j + 1) % 3, (j + 2) % 3);
+        m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+    };
+    let determinant = (0..3).map(|j| m[0][j] * cofactor(0, j)).sum::<f64>();
+
+    let mut inverse = [[0.0; 3]; 3];
+    for (i, row) in inverse.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = cofactor(j, i) / determinant;
+        }
+    }
+    inverse
+}
+
+/// Writes an `EDGE_SE2` with the upper triangle of its information matrix.
+fn write_edge(
+    out: &mut impl Write,
+    from: usize,
+    to: usize,
+    measurement: MetricPose,
+    information: [[f64; 3]; 3],
+) -> IoResult<()> {
+    writeln!(
+        out,
+        "EDGE_SE2 {} {} {:.6} {:.6} {:.6} {:.6} {:.6} {:.6} {:.6} {:.6} {:.6}",
+        from,
+        to,
+        measurement.x,
+        measurement.y,
+        measurement.yaw,
+        information[0][0],
+        information[0][1],
+        information[0][2],
+        information[1][1],
+        information[1][2],
+        information[2][2]
+    )
+}
+
+/// Exports each robot's run as a g2o pose graph, with a `VERTEX_SE2` per scan, an `EDGE_SE2`
+/// per step of the simulated odometry and an `EDGE_SE2` per ground-truth loop closure.
+///
+/// The vertices start at the odometry poses, as the initial guess for the back-end, with the
+/// first one fixed; the ground truth to compare the optimized poses with is `lidar_poses_N.json`,
+/// or the TUM and KITTI exports. The odometry edges' information matrices come from linearizing
+/// the odometry noise model, and the loop closures' measurements are the ground-truth relative
+/// poses. Like the other exports, the graph is in metres in a right-handed frame.
+pub struct G2oExporter {
+    /// Scale from the simulation's pixels to metres.
+    pub metres_per_pixel: f64,
+}
+
+impl G2oExporter {
+    pub fn new(metres_per_pixel: f64) -> Self {
+        Self { metres_per_pixel }
+    }
+
+    /// Information matrix of an odometry step, in metres in the right-handed frame.
+    fn odometry_information(&self, noise: OdometryNoise, p0: Pose2D, p1: Pose2D) -> [[f64; 3]; 3] {
+        let covariance = noise.covariance(p0, p1);
+
+        // Mirroring negates y and the heading, and the translations are scaled to metres
+        let scale = [self.metres_per_pixel, -self.metres_per_pixel, -1.0];
+        let mut metric = [[0.0; 3]; 3];
+        for (j, row) in metric.iter_mut().enumerate() {
+            for (k, value) in row.iter_mut().enumerate() {
+                *value = scale[j] * covariance[j][k] as f64 * scale[k];
+            }
+        }
+        metric[0][0] += MIN_TRANSLATION_SIGMA.powi(2);
+        metric[1][1] += MIN_TRANSLATION_SIGMA.powi(2);
+        metric[2][2] += MIN_ROTATION_SIGMA.powi(2);
+
+        invert(metric)
+    }
+
+    fn write_graph(
+        &self,
+        out: &mut impl Write,
+        run: &RunRecord,
+        robot: &RobotRecord,
+    ) -> IoResult<()> {
+        let to_metric = |pose| MetricPose::from_pose(pose, self.metres_per_pixel);
+
+        for (i, pose) in robot.odometry.iter().enumerate() {
+            let pose = to_metric(*pose);
+            writeln!(
+                out,
+                "VERTEX_SE2 {} {:.6} {:.6} {:.6}",
+                i, pose.x, pose.y, pose.yaw
+            )?;
+        }
+        if !robot.odometry.is_empty() {
+            writeln!(out, "FIX 0")?;
+        }
+
+        for i in 1..robot.odometry.len() {
+            let measurement =
+                to_metric(robot.odometry[i]).relative_to(to_metric(robot.odometry[i - 1]));
+            let information = self.odometry_information(
+                run.odometry_noise,
+                robot.odometry[i - 1],
+                robot.odometry[i],
+            );
+            write_edge(out, i - 1, i, measurement, information)?;
+        }
+
+        let translation = LOOP_CLOSURE_TRANSLATION_SIGMA.powi(-2);
+        let rotation = LOOP_CLOSURE_ROTATION_SIGMA.powi(-2);
+        let information = [
+            [translation, 0.0, 0.0],
+            [0.0, translation, 0.0],
+            [0.0, 0.0, rotation],
+        ];
+        for closure in robot.loop_closures.iter() {
+            let (from, to) = (closure.revisited_scan, closure.scan);
+            let measurement = to_metric(robot.poses[to]).relative_to(to_metric(robot.poses[from]));
+            write_edge(out, from, to, measurement, information)?;
+        }
+
+        out.flush()
+    }
+}
+
+impl Exporter for G2oExporter {
+    fn export(&self, run: &RunRecord) -> IoResult<Vec<String>> {
+        let mut filenames = Vec::new();
+        for (r, robot) in run.robots.iter().enumerate() {
+            let filename = run.robot_filename("graph", r, "g2o");
+            let file = File::create(&filename)?;
+            self.write_graph(&mut BufWriter::new(file), run, robot)?;
+            filenames.push(filename);
+        }
+        Ok(filenames)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inverse_is_the_inverse() {
+        let m = [[4.0, 1.0, 0.5], [1.0, 3.0, 0.2], [0.5, 0.2, 2.0]];
+        let inverse = invert(m);
+        let product = |i: usize, j: usize| (0..3).map(|k| m[i][k] * inverse[k][j]).sum::<f64>();
+        for i in 0..3 {
+            for j in 0..3 {
+                let identity = if i == j { 1.0 } else { 0.0 };
+                assert!((product(i, j) - identity).abs() < 1E-12);
+            }
+        }
+    }
+
+    #[test]
+    fn edges_hold_the_relative_pose_and_upper_information() {
+        let from = MetricPose {
+            x: 1.0,
+            y: 2.0,
+            yaw: 0.5,
+        };
+        let to = MetricPose {
+            x: 1.5,
+            y: 3.0,
+            yaw: 2.0,
+        };
+        let measurement = to.relative_to(from);
+
+        // Composing the measurement onto the first pose gives back the second
+        let (sin, cos) = from.yaw.sin_cos();
+        let x = from.x + cos * measurement.x - sin * measurement.y;
+        let y = from.y + sin * measurement.x + cos * measurement.y;
+        assert!((x - to.x).abs() < 1E-12 && (y - to.y).abs() < 1E-12);
+        assert!((from.yaw + measurement.yaw - to.yaw).abs() < 1E-12);
+
+        let information = [[1.0, 2.0, 3.0], [2.0, 4.0, 5.0], [3.0, 5.0, 6.0]];
+        let mut out = Vec::new();
+        write_edge(&mut out, 3, 4, measurement, information).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!(
+                "EDGE_SE2 3 4 {:.6} {:.6} 1.500000 1.000000 2.000000 3.000000 4.000000 5.000000 6.000000\n",
+                measurement.x, measurement.y
+            )
+        );
+    }
+}
@@ -0,0 +1,278 @@
+use godot::prelude::*;
+
+/// Returns true if the point lies inside the polygon (even-odd rule).
+pub fn point_in_polygon(point: Vector2, polygon: &[Vector2]) -> bool {
+    let mut inside = false;
+    let n = polygon.len();
+    if n < 3 {
+        return false;
+    }
+
+    let mut j = n - 1;
+    for i in 0..n {
+        let (a, b) = (polygon[i], polygon[j]);
+        if (a.y > point.y) != (b.y > point.y)
+            && point.x < (b.x - a.x) * (point.y - a.y) / (b.y - a.y) + a.x
+        {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+/// Returns true if the segments `p1`-`p2` and `q1`-`q2` intersect.
+pub fn segments_intersect(p1: Vector2, p2: Vector2, q1: Vector2, q2: Vector2) -> bool {
+    let d1 = (p2 - p1).cross(q1 - p1);
+    let d2 = (p2 - p1).cross(q2 - p1);
+    let d3 = (q2 - q1).cross(p1 - q1);
+    let d4 = (q2 - q1).cross(p2 - q1);
+
+    if ((d1 > 0.0 && d2 < 0.0) || (d1 < 0.0 && d2 > 0.0))
+        && ((d3 > 0.0 && d4 < 0.0) || (d3 < 0.0 && d4 > 0.0))
+    {
+        return true;
+    }
+
+    // Collinear or touching cases
+    (d1 == 0.0 && on_segment(p1, p2, q1))
+        || (d2 == 0.0 && on_segment(p1, p2, q2))
+        || (d3 == 0.0 && on_segment(q1, q2, p1))
+        || (d4 == 0.0 && on_segment(q1, q2, p2))
+}
+
+fn on_segment(a: Vector2, b: Vector2, p: Vector2) -> bool {
+    p.x >= a.x.min(b.x) && p.x <= a.x.max(b.x) && p.y >= a.y.min(b.y) && p.y <= a.y.max(b.y)
+}
+
+/// Shortest distance from a point to the segment `a`-`b`.
+pub fn point_segment_distance(p: Vector2, a: Vector2, b: Vector2) -> f32 {
+    let ab = b - a;
+    let len_sq = ab.length_squared();
+    if len_sq == 0.0 {
+        return p.distance_to(a);
+    }
+    let t = ((p - a).dot(ab) / len_sq).clamp(0.0, 1.0);
+    p.distance_to(a + ab * t)
+}
+
+/// Shortest distance between the segments `p1`-`p2` and `q1`-`q2`.
+pub fn segment_segment_distance(p1: Vector2, p2: Vector2, q1: Vector2, q2: Vector2) -> f32 {
+    if segments_intersect(p1, p2, q1, q2) {
+        return 0.0;
+    }
+    point_segment_distance(p1, q1, q2)
+        .min(point_segment_distance(p2, q1, q2))
+        .min(point_segment_distance(q1, p1, p2))
+        .min(point_segment_distance(q2, p1, p2))
+}
+
+/// Iterates over the edges of a closed polygon as `(start, end)` pairs.
+pub fn polygon_edges(polygon: &[Vector2]) -> impl Iterator<Item = (Vector2, Vector2)> + '_ {
+    (0..polygon.len()).map(move |i| (polygon[i], polygon[(i + 1) % polygon.len()]))
+}
+
+/// Distance along a ray from `origin` in the unit `direction` to each polygon edge it crosses,
+/// with the index of that edge. Crossings closer than 1E-3 are ignored so that rays starting on an
+/// edge don't hit it.
+fn ray_crossings<'a>(
+    origin: Vector2,
+    direction: Vector2,
+    polygon: &'a [Vector2],
+) -> impl Iterator<Item = (f32, usize)> + 'a {
+    polygon_edges(polygon)
+        .enumerate()
+        .filter_map(move |(idx, (a, b))| {
+            let edge = b - a;
+            let denom = direction.cross(edge);
+            if denom.abs() < 1E-9 {
+                return None; // Parallel
+            }
+
+            let to_edge = a - origin;
+            let t = to_edge.cross(edge) / denom;
+            let u = to_edge.cross(direction) / denom;
+            (t > 1E-3 && (0.0..=1.0).contains(&u)).then_some((t, idx))
+        })
+}
+
+/// Distance along a ray from `origin` in the unit `direction` to the first polygon edge it crosses,
+/// with the index of that edge. Crossings closer than 1E-3 are ignored so that rays starting on an
+/// edge don't hit it.
+pub fn ray_polygon_distance(
+    origin: Vector2,
+    direction: Vector2,
+    polygon: &[Vector2],
+) -> Option<(f32, usize)> {
+    ray_crossings(origin, direction, polygon).min_by(|a, b| a.0.total_cmp(&b.0))
+}
+
+/// Like `ray_polygon_distance`, but only counting the edges the ray enters the polygon through, so
+/// that a ray starting inside the polygon doesn't hit it on the way out.
+pub fn ray_polygon_entry_distance(
+    origin: Vector2,
+    direction: Vector2,
+    polygon: &[Vector2],
+) -> Option<(f32, usize)> {
+    // The outward normals are on the right of the edges of a polygon with positive signed area
+    let orientation = polygon_edges(polygon)
+        .map(|(a, b)| a.cross(b))
+        .sum::<f32>()
+        .signum();
+    ray_crossings(origin, direction, polygon)
+        .filter(|&(_, idx)| {
+            let edge = polygon[(idx + 1) % polygon.len()] - polygon[idx];
+            let outward = Vector2::new(edge.y, -edge.x) * orientation;
+            outward.dot(direction) < 0.0
+        })
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+}
+
+/// Unit normal of the edge `a`-`b`, on the side facing back along the ray `direction`.
+pub fn edge_normal(a: Vector2, b: Vector2, direction: Vector2) -> Vector2 {
+    let edge = b - a;
+    let normal = Vector2::new(-edge.y, edge.x).normalized();
+    if normal.dot(direction) > 0.0 {
+        -normal
+    } else {
+        normal
+    }
+}
+
+/// Unit normal of edge `edge` of a polygon, as returned by `ray_polygon_distance`, facing the ray.
+pub fn polygon_edge_normal(polygon: &[Vector2], edge: usize, direction: Vector2) -> Vector2 {
+    edge_normal(
+        polygon[edge],
+        polygon[(edge + 1) % polygon.len()],
+        direction,
+    )
+}
+
+/// Angle between a ray `direction` and the surface `normal` it hits, zero for a head-on hit.
+pub fn incidence_angle(direction: Vector2, normal: Vector2) -> f32 {
+    (-direction.dot(normal)).clamp(-1.0, 1.0).acos()
+}
+
+/// Returns true if the segment `a`-`b` stays at least `clearance` away from every polygon.
+pub fn segment_is_free(a: Vector2, b: Vector2, polygons: &[Vec<Vector2>], clearance: f32) -> bool {
+    for polygon in polygons.iter() {
+        if point_in_polygon(a, polygon) || point_in_polygon(b, polygon) {
+            return false;
+        }
+        for (e1, e2) in polygon_edges(polygon) {
+            if segments_intersect(a, b, e1, e2)
+                || segment_segment_distance(a, b, e1, e2) < clearance
+            {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+/// Resamples a polyline so that consecutive points are at most `spacing` apart.
+pub fn densify_path(path: &[Vector2], spacing: f32) -> Vec<Vector2> {
+    let mut dense = Vec::new();
+    for window in path.windows(2) {
+        let (a, b) = (window[0], window[1]);
+        let steps = ((a.distance_to(b) / spacing).ceil() as usize).max(1);
+        for k in 0..steps {
+            dense.push(a.lerp(b, k as f32 / steps as f32));
+        }
+    }
+    if let Some(last) = path.last() {
+        dense.push(*last);
+    }
+    dense
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(min: f32, max: f32) -> Vec<Vector2> {
+        vec![
+            Vector2::new(min, min),
+            Vector2::new(max, min),
+            Vector2::new(max, max),
+            Vector2::new(min, max),
+        ]
+    }
+
+    #[test]
+    fn points_inside_and_outside() {
+        let polygon = square(0.0, 10.0);
+        assert!(point_in_polygon(Vector2::new(5.0, 5.0), &polygon));
+        assert!(!point_in_polygon(Vector2::new(15.0, 5.0), &polygon));
+        assert!(!point_in_polygon(Vector2::new(5.0, 5.0), &polygon[..2]));
+    }
+
+    #[test]
+    fn segment_intersections() {
+        let (a, b) = (Vector2::new(0.0, 0.0), Vector2::new(10.0, 10.0));
+        assert!(segments_intersect(
+            a,
+            b,
+            Vector2::new(0.0, 10.0),
+            Vector2::new(10.0, 0.0)
+        ));
+        assert!(!segments_intersect(
+            a,
+            b,
+            Vector2::new(1.0, 0.0),
+            Vector2::new(11.0, 10.0)
+        ));
+        assert!(segments_intersect(a, b, b, Vector2::new(20.0, 0.0)));
+        assert!(
+            (segment_segment_distance(a, b, Vector2::new(0.0, 2.0), Vector2::new(0.0, 5.0))
+                - 2.0_f32.sqrt())
+            .abs()
+                < 1E-5
+        );
+    }
+
+    #[test]
+    fn rays_hit_the_nearest_edge() {
+        let polygon = square(10.0, 20.0);
+        let (distance, edge) =
+            ray_polygon_distance(Vector2::new(0.0, 15.0), Vector2::new(1.0, 0.0), &polygon)
+                .unwrap();
+        assert!((distance - 10.0).abs() < 1E-5);
+        assert_eq!(edge, 3);
+
+        let normal = polygon_edge_normal(&polygon, edge, Vector2::new(1.0, 0.0));
+        assert!((normal - Vector2::new(-1.0, 0.0)).length() < 1E-5);
+        assert!(incidence_angle(Vector2::new(1.0, 0.0), normal).abs() < 1E-3);
+
+        assert!(
+            ray_polygon_distance(Vector2::new(0.0, 15.0), Vector2::new(-1.0, 0.0), &polygon)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn free_segments_keep_their_clearance() {
+        let polygons = vec![square(10.0, 20.0)];
+        let (a, b) = (Vector2::new(0.0, 25.0), Vector2::new(30.0, 25.0));
+        assert!(segment_is_free(a, b, &polygons, 4.0));
+        assert!(!segment_is_free(a, b, &polygons, 6.0));
+        assert!(!segment_is_free(
+            Vector2::new(0.0, 15.0),
+            Vector2::new(30.0, 15.0),
+            &polygons,
+            0.0
+        ));
+    }
+
+    #[test]
+    fn densified_paths_keep_their_ends() {
+        let path = [Vector2::new(0.0, 0.0), Vector2::new(10.0, 0.0)];
+        let dense = densify_path(&path, 3.0);
+        assert_eq!(dense.len(), 5);
+        assert_eq!(dense.first(), path.first());
+        assert_eq!(dense.last(), path.last());
+        assert!(dense
+            .windows(2)
+            .all(|w| w[0].distance_to(w[1]) <= 3.0 + 1E-5));
+    }
+}
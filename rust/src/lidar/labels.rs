@@ -0,0 +1,60 @@
+/// Class of the object a beam hit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ObjectClass {
+    /// The beam reached its maximum range without hitting anything.
+    None,
+    /// One of the arena walls.
+    Wall,
+    /// A random square.
+    Box,
+    /// A random circle.
+    Circle,
+    /// A dynamic obstacle.
+    Dynamic,
+    /// A robot body, either another robot or the robot's own.
+    Robot,
+}
+
+impl ObjectClass {
+    /// Integer code of the class in the label output.
+    pub fn code(&self) -> i64 {
+        match self {
+            ObjectClass::None => 0,
+            ObjectClass::Wall => 1,
+            ObjectClass::Box => 2,
+            ObjectClass::Circle => 3,
+            ObjectClass::Dynamic => 4,
+            ObjectClass::Robot => 5,
+        }
+    }
+
+    /// Whether objects of this class move.
+    pub fn is_dynamic(&self) -> bool {
+        matches!(self, ObjectClass::Dynamic | ObjectClass::Robot)
+    }
+}
+
+/// Ground-truth label of a single beam return.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BeamLabel {
+    /// Index of the object within its kind: the static polygon, the dynamic obstacle (as in the
+    /// dynamic tracks) or the robot, or -1 for no hit.
+    pub id: i64,
+    pub class: ObjectClass,
+}
+
+impl BeamLabel {
+    pub const NONE: BeamLabel = BeamLabel {
+        id: -1,
+        class: ObjectClass::None,
+    };
+
+    pub fn new(id: i64, class: ObjectClass) -> Self {
+        Self { id, class }
+    }
+
+    /// The label as a row of id, class code and dynamic flag.
+    pub fn row(&self) -> [i64; 3] {
+        [self.id, self.class.code(), self.class.is_dynamic() as i64]
+    }
+}
@@ -1,33 +1,104 @@
-use godot::classes::{Line2D, RayCast2D};
+use crate::lidar::motion::{MotionModel, Pose2D};
+use crate::lidar::occupancy_grid::OccupancyGrid;
+use crate::lidar::pure_pursuit::PurePursuit;
+use crate::lidar::scan_writer::ScanWriter;
+use crate::lidar::sensor::SensorConfig;
+use godot::classes::{Line2D, Polygon2D, RayCast2D};
 use godot::prelude::*;
 use ndarray::Array2;
 
-/// A struct to encapsulate the state variables for the Lidar.
-pub struct LidarState {
+/// The rays and recorded scans of one sensor on the robot.
+pub struct SensorState {
+    pub config: SensorConfig,
+    pub beam_angles: Vec<f32>,
     pub rays: Vec<Gd<RayCast2D>>,
     pub lines: Vec<Gd<Line2D>>,
+    pub returns: Vec<Array2<f64>>, // Only kept for the exporters, which need the whole run
+    pub labels: Vec<Array2<i64>>,  // Id, class and dynamic flag of the object each beam hit
+    pub poses: Vec<Pose2D>,
+    pub returns_writer: Option<ScanWriter>,
+    pub labels_writer: Option<ScanWriter>,
+    pub points_writer: Option<ScanWriter>, // Hit points in the sensor frame
+    pub world_points_writer: Option<ScanWriter>, // Hit points in the world frame
+}
+
+impl SensorState {
+    pub fn new(config: SensorConfig) -> Self {
+        Self {
+            config,
+            beam_angles: config.beams.beam_angles(),
+            rays: Vec::<Gd<RayCast2D>>::new(),
+            lines: Vec::<Gd<Line2D>>::new(),
+            returns: Vec::<Array2<f64>>::new(),
+            labels: Vec::<Array2<i64>>::new(),
+            poses: Vec::<Pose2D>::new(),
+            returns_writer: None,
+            labels_writer: None,
+            points_writer: None,
+            world_points_writer: None,
+        }
+    }
+
+    /// Moves the rays to the sensor pose and points them along their beams.
+    pub fn orient_rays(&mut self, pose: Pose2D) {
+        let max_range = self.config.beams.max_range;
+        for (ray, angle) in self.rays.iter_mut().zip(self.beam_angles.iter()) {
+            ray.set_position(pose.position);
+            ray.set_target_position(Vector2::from_angle(angle + pose.heading) * max_range);
+        }
+    }
+}
+
+/// A struct to encapsulate the state variables of one robot carrying the Lidar.
+pub struct LidarState {
+    pub sensors: Vec<SensorState>,
+    pub merged_writer: Option<ScanWriter>,
     pub path: Vec<Vector2>,
     pub path_idx: usize,
     pub angle: f32,
     pub target_angle: f32,
     pub slewing: bool,
     pub slew_rate: f32,
-    pub returns: Vec<Array2<f64>>,
+    pub scan_poses: Vec<Pose2D>,
+    pub scan_times: Vec<f64>,
+    pub odometry: Vec<Pose2D>, // Simulated odometry pose at each scan
+    pub pose: Pose2D,
+    pub time: f64,
+    pub vehicle: Option<Box<dyn MotionModel>>,
+    pub controller: PurePursuit,
+    pub body_polygon: Option<Gd<Polygon2D>>,
+    pub map: OccupancyGrid,
+    pub exploration_goals: u32,
+    pub start_index: i64,
+    pub end_index: i64,
+    pub finished: bool,
 }
 
 impl LidarState {
     /// Creates a new `LidarState` with default values.
     pub fn new() -> Self {
         Self {
-            rays: Vec::<Gd<RayCast2D>>::new(),
-            lines: Vec::<Gd<Line2D>>::new(),
+            sensors: Vec::<SensorState>::new(),
+            merged_writer: None,
             path: Vec::<Vector2>::new(),
             path_idx: 0,
             angle: 0.0,
             target_angle: 0.0,
             slewing: false,
-            slew_rate: 30.0, // degrees per second
-            returns: Vec::<Array2<f64>>::new(),
+            slew_rate: 30.0, // radians per second
+            scan_poses: Vec::<Pose2D>::new(),
+            scan_times: Vec::<f64>::new(),
+            odometry: Vec::<Pose2D>::new(),
+            pose: Pose2D::new(Vector2::ZERO, 0.0),
+            time: 0.0,
+            vehicle: None,
+            controller: PurePursuit::new(),
+            body_polygon: None,
+            map: OccupancyGrid::new(100, 100, 1024. / 100.),
+            exploration_goals: 0,
+            start_index: 0,
+            end_index: 0,
+            finished: false,
         }
     }
 }
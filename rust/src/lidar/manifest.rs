@@ -0,0 +1,176 @@
+use std::collections::BTreeMap;
+use std::io::Result as IoResult;
+
+use crate::lidar::sensor::ScanInfo;
+use crate::lidar::serializer::write_to_json;
+use serde::Serialize;
+
+/// Version of the simulator, and the commit it was built from.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const GIT_HASH: &str = env!("LIDAR_GIT_HASH");
+
+/// Outcome of an iteration.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IterationStatus {
+    /// All the outputs were written.
+    Success,
+    /// Not every robot was placed or found a path, so nothing was written and the iteration was
+    /// retried.
+    Failed,
+}
+
+/// One iteration of the run, as listed in the manifest.
+#[derive(Clone, Debug, Serialize)]
+pub struct IterationRecord {
+    pub iteration: u32,
+    pub status: IterationStatus,
+    /// Seed of the random generator for this iteration.
+    pub seed: u64,
+    /// Values drawn for the randomized settings.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub randomized: BTreeMap<String, String>,
+    /// Number of scans recorded by each robot.
+    pub frames: Vec<usize>,
+    /// Output files, relative to the output directory.
+    pub files: Vec<String>,
+}
+
+/// Units of the values in the outputs.
+#[derive(Serialize)]
+pub struct Units {
+    pub distance: &'static str,
+    pub angle: &'static str,
+    pub time: &'static str,
+    /// Scale used to convert to metres in the ROS and CARMEN exports.
+    pub metres_per_pixel: f64,
+}
+
+/// Description of a run and its outputs, written to `manifest.json` in the output directory.
+#[derive(Serialize)]
+pub struct Manifest {
+    pub version: &'static str,
+    pub git_hash: &'static str,
+    /// Seed of the run, from which each iteration's seed is derived.
+    pub seed: u64,
+    /// The command-line arguments as given.
+    pub arguments: BTreeMap<String, String>,
+    /// Settings given as ranges or choices, drawn anew on every iteration.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub randomized: BTreeMap<String, String>,
+    /// Every setting, including the defaults that were not given, with ranges and choices as
+    /// given. Saved as `.json`, it can be read back with `--config`.
+    pub config: serde_json::Value,
+    /// The sensors of the latest iteration.
+    pub sensors: Vec<ScanInfo>,
+    pub units: Units,
+    pub conventions: BTreeMap<&'static str, &'static str>,
+    /// The contents of each kind of output file.
+    pub files: BTreeMap<&'static str, String>,
+    pub iterations: Vec<IterationRecord>,
+}
+
+impl Manifest {
+    pub fn write(&self, out_dir: &str) -> IoResult<()> {
+        write_to_json(&format!("{}/manifest.json", out_dir), self)
+    }
+}
+
+/// The coordinate conventions shared by the outputs.
+pub fn conventions() -> BTreeMap<&'static str, &'static str> {
+    [
+        (
+            "world_frame",
+            "Godot 2D: x to the right, y down, origin at the top left of the 1024 x 1024 px arena",
+        ),
+        (
+            "heading",
+            "Angle from the +x axis towards +y (clockwise on screen), wrapped to [-pi, pi)",
+        ),
+        (
+            "base_frame",
+            "x forward along the heading, y to the robot's right (towards world +y when the \
+             heading is zero)",
+        ),
+        (
+            "bearing",
+            "Relative to the sensor heading, or to the world +x axis with --world_bearings",
+        ),
+        (
+            "exports",
+            "The MCAP, CARMEN, TUM, KITTI and g2o exports are mirrored into right-handed frames \
+             in metres, negating y and the angles",
+        ),
+    ]
+    .into_iter()
+    .collect()
+}
+
+/// The contents of each kind of output file, `N` being the iteration.
+pub fn file_descriptions(normals: bool) -> BTreeMap<&'static str, String> {
+    let returns = if normals {
+        "range, bearing, normal x, normal y, incidence angle"
+    } else {
+        "range, bearing"
+    };
+
+    [
+        (
+            "lidar_returns_N.jsonl",
+            format!(
+                "One scan per line, a row of {} per beam; the range is the max range for no hit \
+                 and null where invalid",
+                returns
+            ),
+        ),
+        (
+            "lidar_labels_N.jsonl",
+            "One scan per line, a row of object id, class and dynamic flag per beam".to_string(),
+        ),
+        (
+            "lidar_points_N.jsonl",
+            "One scan per line, a row of x, y per hit point in the sensor frame".to_string(),
+        ),
+        (
+            "lidar_world_points_N.jsonl",
+            "One scan per line, a row of x, y per hit point in the world frame".to_string(),
+        ),
+        (
+            "lidar_returns_N_merged.jsonl",
+            "One merged scan per line, a row of range, bearing per hit in the base frame"
+                .to_string(),
+        ),
+        (
+            "lidar_poses_N.json",
+            "A row of time, x, y, heading per scan for the robot base".to_string(),
+        ),
+        (
+            "lidar_sensor_poses_N.json",
+            "A row of time, x, y, heading per scan for the sensor".to_string(),
+        ),
+        (
+            "lidar_odometry_N.json",
+            "A row of time, x, y, heading per scan of simulated odometry".to_string(),
+        ),
+        (
+            "lidar_path_N.json",
+            "A row of x, y per point of the planned path".to_string(),
+        ),
+        (
+            "lidar_loop_closures_N.json",
+            "Pairs of scan indices at the same place".to_string(),
+        ),
+        (
+            "lidar_dynamic_tracks_N.json",
+            "Shape, pattern, size and a row of time, x, y, heading per step of each moving \
+             obstacle"
+                .to_string(),
+        ),
+        (
+            "lidar_scan_info_N.json",
+            "Beam layout and mount of each sensor".to_string(),
+        ),
+    ]
+    .into_iter()
+    .collect()
+}
@@ -0,0 +1,436 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Result as IoResult};
+
+use crate::lidar::cdr::CdrWriter;
+use crate::lidar::export::{Exporter, MetricPose, RunRecord};
+use crate::lidar::motion::Pose2D;
+use crate::lidar::occupancy_grid::OccupancyGrid;
+use crate::lidar::sensor::BeamModel;
+use mcap::records::MessageHeader;
+
+const SEPARATOR: &str =
+    "================================================================================\n";
+
+const TIME: &str = "int32 sec\nuint32 nanosec\n";
+const HEADER: &str = "builtin_interfaces/Time stamp\nstring frame_id\n";
+const POINT: &str = "float64 x\nfloat64 y\nfloat64 z\n";
+const QUATERNION: &str = "float64 x 0\nfloat64 y 0\nfloat64 z 0\nfloat64 w 1\n";
+const VECTOR3: &str = "float64 x\nfloat64 y\nfloat64 z\n";
+const POSE: &str = "geometry_msgs/Point position\ngeometry_msgs/Quaternion orientation\n";
+
+const LASER_SCAN: &str = "std_msgs/Header header\nfloat32 angle_min\nfloat32 angle_max\n\
+float32 angle_increment\nfloat32 time_increment\nfloat32 scan_time\nfloat32 range_min\n\
+float32 range_max\nfloat32[] ranges\nfloat32[] intensities\n";
+const ODOMETRY: &str = "std_msgs/Header header\nstring child_frame_id\n\
+geometry_msgs/PoseWithCovariance pose\ngeometry_msgs/TwistWithCovariance twist\n";
+const TF_MESSAGE: &str = "geometry_msgs/TransformStamped[] transforms\n";
+const OCCUPANCY_GRID: &str = "std_msgs/Header header\nnav_msgs/MapMetaData info\nint8[] data\n";
+
+/// Full `ros2msg` schema of a message: its definition followed by those of the types it uses.
+fn schema(definition: &str, dependencies: &[(&str, &str)]) -> String {
+    let mut text = definition.to_string();
+    for (name, dependency) in dependencies.iter() {
+        text += SEPARATOR;
+        text += &format!("MSG: {}\n", name);
+        text += dependency;
+    }
+    text
+}
+
+fn laser_scan_schema() -> String {
+    schema(
+        LASER_SCAN,
+        &[
+            ("std_msgs/Header", HEADER),
+            ("builtin_interfaces/Time", TIME),
+        ],
+    )
+}
+
+fn odometry_schema() -> String {
+    schema(
+        ODOMETRY,
+        &[
+            ("std_msgs/Header", HEADER),
+            ("builtin_interfaces/Time", TIME),
+            (
+                "geometry_msgs/PoseWithCovariance",
+                "geometry_msgs/Pose pose\nfloat64[36] covariance\n",
+            ),
+            ("geometry_msgs/Pose", POSE),
+            ("geometry_msgs/Point", POINT),
+            ("geometry_msgs/Quaternion", QUATERNION),
+            (
+                "geometry_msgs/TwistWithCovariance",
+                "geometry_msgs/Twist twist\nfloat64[36] covariance\n",
+            ),
+            (
+                "geometry_msgs/Twist",
+                "geometry_msgs/Vector3 linear\ngeometry_msgs/Vector3 angular\n",
+            ),
+            ("geometry_msgs/Vector3", VECTOR3),
+        ],
+    )
+}
+
+fn tf_message_schema() -> String {
+    schema(
+        TF_MESSAGE,
+        &[
+            (
+                "geometry_msgs/TransformStamped",
+                "std_msgs/Header header\nstring child_frame_id\ngeometry_msgs/Transform transform\n",
+            ),
+            ("std_msgs/Header", HEADER),
+            ("builtin_interfaces/Time", TIME),
+            (
+                "geometry_msgs/Transform",
+                "geometry_msgs/Vector3 translation\ngeometry_msgs/Quaternion rotation\n",
+            ),
+            ("geometry_msgs/Vector3", VECTOR3),
+            ("geometry_msgs/Quaternion", QUATERNION),
+        ],
+    )
+}
+
+fn occupancy_grid_schema() -> String {
+    schema(
+        OCCUPANCY_GRID,
+        &[
+            ("std_msgs/Header", HEADER),
+            ("builtin_interfaces/Time", TIME),
+            (
+                "nav_msgs/MapMetaData",
+                "builtin_interfaces/Time map_load_time\nfloat32 resolution\nuint32 width\n\
+uint32 height\ngeometry_msgs/Pose origin\n",
+            ),
+            ("geometry_msgs/Pose", POSE),
+            ("geometry_msgs/Point", POINT),
+            ("geometry_msgs/Quaternion", QUATERNION),
+        ],
+    )
+}
+
+fn time_nanos(t: f64) -> u64 {
+    (t * 1E9).round().max(0.0) as u64
+}
+
+fn write_time(cdr: &mut CdrWriter, t: f64) {
+    let nanos = time_nanos(t);
+    cdr.i32((nanos / 1_000_000_000) as i32);
+    cdr.u32((nanos % 1_000_000_000) as u32);
+}
+
+fn write_header(cdr: &mut CdrWriter, t: f64, frame_id: &str) {
+    write_time(cdr, t);
+    cdr.string(frame_id);
+}
+
+fn write_pose(cdr: &mut CdrWriter, pose: MetricPose) {
+    cdr.f64(pose.x);
+    cdr.f64(pose.y);
+    cdr.f64(0.0);
+    write_yaw_quaternion(cdr, pose.yaw);
+}
+
+fn write_yaw_quaternion(cdr: &mut CdrWriter, yaw: f64) {
+    cdr.f64(0.0);
+    cdr.f64(0.0);
+    cdr.f64((yaw / 2.0).sin());
+    cdr.f64((yaw / 2.0).cos());
+}
+
+fn write_transform(cdr: &mut CdrWriter, t: f64, parent: &str, child: &str, pose: MetricPose) {
+    write_header(cdr, t, parent);
+    cdr.string(child);
+
+    // A transform has the same layout as a pose, a translation followed by a rotation
+    write_pose(cdr, pose);
+}
+
+/// Exports each iteration as a ROS 2 bag in MCAP format, with `sensor_msgs/LaserScan`,
+/// `nav_msgs/Odometry` ground truth, `tf2_msgs/TFMessage` and the map as `nav_msgs/OccupancyGrid`.
+///
+/// Godot's y axis points down, so the world is mirrored into ROS's right-handed frames: y and
+/// the angles change sign, and the beams of each scan are reversed so the angles increase.
+pub struct McapExporter {
+    /// Scale from the simulation's pixels to metres.
+    pub metres_per_pixel: f64,
+}
+
+impl McapExporter {
+    pub fn new(metres_per_pixel: f64) -> Self {
+        Self { metres_per_pixel }
+    }
+
+    fn to_ros(&self, pose: Pose2D) -> MetricPose {
+        MetricPose::from_pose(pose, self.metres_per_pixel)
+    }
+
+    fn laser_scan(
+        &self,
+        t: f64,
+        frame_id: &str,
+        beams: &BeamModel,
+        ranges: &[f64],
+        scan_time: f64,
+    ) -> Vec<u8> {
+        // Mirroring reverses the direction of increasing beam angle
+        let angle_increment = beams.angle_increment();
+        let angle_max =
+            beams.angle_min() + beams.n_beams.saturating_sub(1) as f32 * angle_increment;
+        let max_range = beams.max_range as f64;
+
+        // Beams that hit nothing report infinity, following REP 117
+        let ranges: Vec<f32> = ranges
+            .iter()
+            .rev()
+            .map(|r| {
+                if *r >= max_range - 1E-3 {
+                    f32::INFINITY
+                } else {
+                    (r * self.metres_per_pixel) as f32
+                }
+            })
+            .collect();
+
+        let mut cdr = CdrWriter::new();
+        write_header(&mut cdr, t, frame_id);
+        cdr.f32(-angle_max);
+        cdr.f32(-beams.angle_min());
+        cdr.f32(angle_increment);
+        cdr.f32(0.0);
+        cdr.f32(scan_time as f32);
+        cdr.f32(0.0);
+        cdr.f32((max_range * self.metres_per_pixel) as f32);
+        cdr.f32_sequence(&ranges);
+        cdr.f32_sequence(&[]);
+        cdr.into_bytes()
+    }
+
+    fn odometry(
+        &self,
+        t: f64,
+        frames: (&str, &str),
+        pose: MetricPose,
+        previous: Option<(f64, MetricPose)>,
+    ) -> Vec<u8> {
+        // Velocities in the base frame, by differencing the ground-truth poses
+        let (vx, vy, angular) = match previous {
+            Some((t0, p0)) => pose.velocity_from(p0, t - t0),
+            None => (0.0, 0.0, 0.0),
+        };
+
+        let mut cdr = CdrWriter::new();
+        write_header(&mut cdr, t, frames.0);
+        cdr.string(frames.1);
+        write_pose(&mut cdr, pose);
+        for _ in 0..36 {
+            cdr.f64(0.0);
+        }
+        cdr.f64(vx);
+        cdr.f64(vy);
+        cdr.f64(0.0);
+        cdr.f64(0.0);
+        cdr.f64(0.0);
+        cdr.f64(angular);
+        for _ in 0..36 {
+            cdr.f64(0.0);
+        }
+        cdr.into_bytes()
+    }
+
+    fn tf_message(&self, transforms: &[(f64, String, String, MetricPose)]) -> Vec<u8> {
+        let mut cdr = CdrWriter::new();
+        cdr.sequence_length(transforms.len());
+        for (t, parent, child, pose) in transforms.iter() {
+            write_transform(&mut cdr, *t, parent, child, *pose);
+        }
+        cdr.into_bytes()
+    }
+
+    fn occupancy_grid(&self, t: f64, map: &OccupancyGrid) -> Vec<u8> {
+        let resolution = map.resolution as f64 * self.metres_per_pixel;
+
+        // Rows run from the bottom of the mirrored map, and cells are centred on the grid points
+        let data: Vec<i8> = (0..map.height)
+            .rev()
+            .flat_map(|j| (0..map.width).map(move |i| (i, j)))
+            .map(|(i, j)| map.get(i, j))
+            .collect();
+        let origin = MetricPose {
+            x: -resolution / 2.0,
+            y: -(map.height as f64 - 0.5) * resolution,
+            yaw: 0.0,
+        };
+
+        let mut cdr = CdrWriter::new();
+        write_header(&mut cdr, t, "map");
+        write_time(&mut cdr, t);
+        cdr.f32(resolution as f32);
+        cdr.u32(map.width as u32);
+        cdr.u32(map.height as u32);
+        write_pose(&mut cdr, origin);
+        cdr.i8_sequence(&data);
+        cdr.into_bytes()
+    }
+
+    /// Encodes every message of the run, as log time, topic, schema name and data.
+    fn messages(&self, run: &RunRecord) -> Vec<(u64, String, &'static str, Vec<u8>)> {
+        let mut messages = Vec::new();
+        let start = run
+            .robots
+            .iter()
+            .filter_map(|robot| robot.times.first())
+            .fold(f64::INFINITY, |a, b| a.min(*b));
+        let start = if start.is_finite() { start } else { 0.0 };
+
+        messages.push((
+            time_nanos(start),
+            "/map".to_string(),
+            "nav_msgs/msg/OccupancyGrid",
+            self.occupancy_grid(start, run.map),
+        ));
+
+        let mut static_transforms = Vec::new();
+        for (r, robot) in run.robots.iter().enumerate() {
+            // Robots are namespaced when there is more than one
+            let (namespace, prefix) = if run.robots.len() > 1 {
+                (format!("/robot{}", r), format!("robot{}/", r))
+            } else {
+                (String::new(), String::new())
+            };
+            let odom = format!("{}odom", prefix);
+            let base_link = format!("{}base_link", prefix);
+
+            static_transforms.push((
+                start,
+                "map".to_string(),
+                odom.clone(),
+                MetricPose {
+                    x: 0.0,
+                    y: 0.0,
+                    yaw: 0.0,
+                },
+            ));
+
+            let mut previous: Option<(f64, MetricPose)> = None;
+            for (t, pose) in robot.times.iter().zip(robot.poses.iter()) {
+                let ros_pose = self.to_ros(*pose);
+                messages.push((
+                    time_nanos(*t),
+                    format!("{}/odom", namespace),
+                    "nav_msgs/msg/Odometry",
+                    self.odometry(*t, (&odom, &base_link), ros_pose, previous),
+                ));
+                messages.push((
+                    time_nanos(*t),
+                    "/tf".to_string(),
+                    "tf2_msgs/msg/TFMessage",
+                    self.tf_message(&[(*t, odom.clone(), base_link.clone(), ros_pose)]),
+                ));
+                previous = Some((*t, ros_pose));
+            }
+
+            for (k, sensor) in robot.sensors.iter().enumerate() {
+                let suffix = if robot.sensors.len() > 1 {
+                    k.to_string()
+                } else {
+                    String::new()
+                };
+                let laser = format!("{}laser{}", prefix, suffix);
+                static_transforms.push((
+                    start,
+                    base_link.clone(),
+                    laser.clone(),
+                    self.to_ros(sensor.config.mount.as_pose()),
+                ));
+
+                let scan_time = match robot.times {
+                    [t0, t1, ..] => t1 - t0,
+                    _ => 0.0,
+                };
+                for (t, returns) in robot.times.iter().zip(sensor.returns.iter()) {
+                    let ranges: Vec<f64> = returns.column(0).to_vec();
+                    messages.push((
+                        time_nanos(*t),
+                        format!("{}/scan{}", namespace, suffix),
+                        "sensor_msgs/msg/LaserScan",
+                        self.laser_scan(*t, &laser, &sensor.config.beams, &ranges, scan_time),
+                    ));
+                }
+            }
+        }
+
+        messages.push((
+            time_nanos(start),
+            "/tf_static".to_string(),
+            "tf2_msgs/msg/TFMessage",
+            self.tf_message(&static_transforms),
+        ));
+
+        // Readers expect the messages in time order
+        messages.sort_by_key(|m| m.0);
+        messages
+    }
+
+    fn write_bag(&self, file: File, run: &RunRecord) -> mcap::McapResult<()> {
+        let mut writer = mcap::Writer::new(BufWriter::new(file))?;
+
+        let schemas: BTreeMap<&str, String> = [
+            ("sensor_msgs/msg/LaserScan", laser_scan_schema()),
+            ("nav_msgs/msg/Odometry", odometry_schema()),
+            ("tf2_msgs/msg/TFMessage", tf_message_schema()),
+            ("nav_msgs/msg/OccupancyGrid", occupancy_grid_schema()),
+        ]
+        .into_iter()
+        .collect();
+
+        let mut schema_ids: BTreeMap<&str, u16> = BTreeMap::new();
+        let mut channels: BTreeMap<String, (u16, u32)> = BTreeMap::new();
+        for (log_time, topic, schema_name, data) in self.messages(run).into_iter() {
+            let schema_id = match schema_ids.get(schema_name) {
+                Some(id) => *id,
+                None => {
+                    let id = writer.add_schema(
+                        schema_name,
+                        "ros2msg",
+                        schemas[schema_name].as_bytes(),
+                    )?;
+                    schema_ids.insert(schema_name, id);
+                    id
+                }
+            };
+
+            if !channels.contains_key(&topic) {
+                let id = writer.add_channel(schema_id, &topic, "cdr", &BTreeMap::new())?;
+                channels.insert(topic.clone(), (id, 0));
+            }
+            let (channel_id, sequence) = channels.get_mut(&topic).unwrap();
+
+            writer.write_to_known_channel(
+                &MessageHeader {
+                    channel_id: *channel_id,
+                    sequence: *sequence,
+                    log_time,
+                    publish_time: log_time,
+                },
+                &data,
+            )?;
+            *sequence += 1;
+        }
+
+        writer.finish()?;
+        Ok(())
+    }
+}
+
+impl Exporter for McapExporter {
+    fn export(&self, run: &RunRecord) -> IoResult<Vec<String>> {
+        let filename = format!("{}/lidar_bag_{}.mcap", run.out_dir, run.iteration);
+        let file = File::create(&filename)?;
+        self.write_bag(file, run).map_err(std::io::Error::other)?;
+        Ok(vec![filename])
+    }
+}
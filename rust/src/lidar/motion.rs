@@ -0,0 +1,387 @@
+use std::f32::consts::{FRAC_PI_2, PI};
+use std::str::FromStr;
+
+use crate::lidar::pure_pursuit::pursuit_curvature;
+use godot::prelude::*;
+
+/// How the Lidar moves along the planned path.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MotionKind {
+    /// Jump from waypoint to waypoint once per frame, slewing in place between segments.
+    Waypoint,
+    /// A differential-drive (unicycle) robot following the path with pure pursuit.
+    DiffDrive,
+    /// A holonomic (mecanum) robot that can translate in any direction, with independent heading.
+    Holonomic,
+    /// A car-like robot with a minimum turning radius.
+    Ackermann,
+}
+
+impl FromStr for MotionKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "waypoint" => Ok(MotionKind::Waypoint),
+            "diff_drive" => Ok(MotionKind::DiffDrive),
+            "holonomic" => Ok(MotionKind::Holonomic),
+            "ackermann" => Ok(MotionKind::Ackermann),
+            _ => Err(format!(
+                "Unknown motion model '{}', expected one of waypoint, diff_drive, holonomic, ackermann",
+                s
+            )),
+        }
+    }
+}
+
+/// Position and heading (radians) of the robot in the world frame.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pose2D {
+    pub position: Vector2,
+    pub heading: f32,
+}
+
+impl Pose2D {
+    pub fn new(position: Vector2, heading: f32) -> Self {
+        Self { position, heading }
+    }
+
+    /// Composes `other`, expressed in this pose's frame, onto this pose.
+    pub fn compose(&self, other: Pose2D) -> Pose2D {
+        Pose2D::new(
+            self.position + other.position.rotated(self.heading),
+            wrap_angle(self.heading + other.heading),
+        )
+    }
+}
+
+/// Wraps an angle in radians to [-pi, pi).
+pub fn wrap_angle(angle: f32) -> f32 {
+    (angle + PI).rem_euclid(2.0 * PI) - PI
+}
+
+/// Velocity and acceleration limits shared by the motion models.
+#[derive(Clone, Copy, Debug)]
+pub struct VelocityLimits {
+    /// Maximum forward speed (px/s).
+    pub max_linear_velocity: f32,
+    /// Maximum turn rate (rad/s).
+    pub max_angular_velocity: f32,
+    /// Maximum change in forward speed (px/s^2).
+    pub max_linear_acceleration: f32,
+    /// Maximum change in turn rate (rad/s^2).
+    pub max_angular_acceleration: f32,
+}
+
+impl VelocityLimits {
+    pub fn new() -> Self {
+        Self {
+            max_linear_velocity: 100.0,
+            max_angular_velocity: 1.5,
+            max_linear_acceleration: 200.0,
+            max_angular_acceleration: 3.0,
+        }
+    }
+
+    /// Highest speed from which the robot can still stop within `distance`.
+    fn stopping_speed(&self, distance: f32) -> f32 {
+        (2.0 * self.max_linear_acceleration * distance).sqrt()
+    }
+}
+
+/// A vehicle model that drives the robot towards a pure pursuit target.
+pub trait MotionModel {
+    /// Steers from `pose` towards `target`, slowing down to stop `distance_to_goal` further
+    /// along the path, and returns the pose `dt` seconds later.
+    fn drive_towards(
+        &mut self,
+        pose: Pose2D,
+        target: Vector2,
+        distance_to_goal: f32,
+        dt: f32,
+    ) -> Pose2D;
+
+    /// The tightest turn the vehicle can make, or zero if it can turn on the spot.
+    fn min_turning_radius(&self) -> f32 {
+        0.0
+    }
+}
+
+/// Moves `current` towards `target` by at most `max_step`.
+fn approach(current: f32, target: f32, max_step: f32) -> f32 {
+    current + (target - current).clamp(-max_step, max_step)
+}
+
+/// Integrates a unicycle moving at `v` and turning at `w` for `dt` seconds.
+fn integrate_unicycle(pose: Pose2D, v: f32, w: f32, dt: f32) -> Pose2D {
+    let heading = pose.heading;
+    let position = if w.abs() < 1E-6 {
+        pose.position + Vector2::new(heading.cos(), heading.sin()) * (v * dt)
+    } else {
+        // Exact integration along the arc
+        let r = v / w;
+        let next_heading = heading + w * dt;
+        pose.position
+            + Vector2::new(
+                r * (next_heading.sin() - heading.sin()),
+                -r * (next_heading.cos() - heading.cos()),
+            )
+    };
+
+    Pose2D::new(position, wrap_angle(heading + w * dt))
+}
+
+/// A unicycle model with velocity and acceleration limits.
+pub struct DifferentialDrive {
+    pub limits: VelocityLimits,
+    pub linear_velocity: f32,
+    pub angular_velocity: f32,
+}
+
+impl DifferentialDrive {
+    pub fn new(limits: VelocityLimits) -> Self {
+        Self {
+            limits,
+            linear_velocity: 0.0,
+            angular_velocity: 0.0,
+        }
+    }
+
+    /// Moves the velocities towards `(v, w)` within the limits, then integrates the pose over `dt` seconds.
+    pub fn step(&mut self, pose: Pose2D, v: f32, w: f32, dt: f32) -> Pose2D {
+        let limits = &self.limits;
+        self.linear_velocity = approach(
+            self.linear_velocity,
+            v,
+            limits.max_linear_acceleration * dt,
+        )
+        .clamp(-limits.max_linear_velocity, limits.max_linear_velocity);
+        self.angular_velocity = approach(
+            self.angular_velocity,
+            w,
+            limits.max_angular_acceleration * dt,
+        )
+        .clamp(-limits.max_angular_velocity, limits.max_angular_velocity);
+
+        integrate_unicycle(pose, self.linear_velocity, self.angular_velocity, dt)
+    }
+}
+
+impl MotionModel for DifferentialDrive {
+    fn drive_towards(
+        &mut self,
+        pose: Pose2D,
+        target: Vector2,
+        distance_to_goal: f32,
+        dt: f32,
+    ) -> Pose2D {
+        let alpha = (target - pose.position).rotated(-pose.heading).angle();
+
+        // Turn in place if the target is behind the robot
+        if alpha.abs() > FRAC_PI_2 {
+            let w = alpha.signum() * self.limits.max_angular_velocity;
+            return self.step(pose, 0.0, w, dt);
+        }
+
+        let curvature = pursuit_curvature(pose, target);
+        let mut v = self.limits.max_linear_velocity;
+        if curvature.abs() > 1E-6 {
+            v = v.min(self.limits.max_angular_velocity / curvature.abs());
+        }
+        v = v.min(self.limits.stopping_speed(distance_to_goal));
+
+        self.step(pose, v, v * curvature, dt)
+    }
+}
+
+/// A holonomic (mecanum) model, translating in any direction while the heading turns independently.
+pub struct Holonomic {
+    pub limits: VelocityLimits,
+    /// Rate at which the heading turns, independent of the direction of travel (rad/s).
+    pub spin_rate: f32,
+    pub velocity: Vector2,
+    pub angular_velocity: f32,
+}
+
+impl Holonomic {
+    pub fn new(limits: VelocityLimits, spin_rate: f32) -> Self {
+        Self {
+            limits,
+            spin_rate,
+            velocity: Vector2::ZERO,
+            angular_velocity: 0.0,
+        }
+    }
+}
+
+impl MotionModel for Holonomic {
+    fn drive_towards(
+        &mut self,
+        pose: Pose2D,
+        target: Vector2,
+        distance_to_goal: f32,
+        dt: f32,
+    ) -> Pose2D {
+        let limits = &self.limits;
+        let speed = limits
+            .max_linear_velocity
+            .min(limits.stopping_speed(distance_to_goal));
+        let desired = (target - pose.position).normalized() * speed;
+
+        // Limit the change in the velocity vector by the acceleration
+        let change = desired - self.velocity;
+        let max_change = limits.max_linear_acceleration * dt;
+        self.velocity += if change.length() > max_change {
+            change.normalized() * max_change
+        } else {
+            change
+        };
+
+        self.angular_velocity = approach(
+            self.angular_velocity,
+            self.spin_rate,
+            limits.max_angular_acceleration * dt,
+        )
+        .clamp(-limits.max_angular_velocity, limits.max_angular_velocity);
+
+        Pose2D::new(
+            pose.position + self.velocity * dt,
+            wrap_angle(pose.heading + self.angular_velocity * dt),
+        )
+    }
+}
+
+/// A kinematic bicycle model of a car-like robot, referenced to the rear axle.
+pub struct Ackermann {
+    pub limits: VelocityLimits,
+    /// Distance between the front and rear axles (px).
+    pub wheelbase: f32,
+    /// Largest steering angle of the front wheels (rad).
+    pub max_steering_angle: f32,
+    pub linear_velocity: f32,
+    pub steering_angle: f32,
+}
+
+impl Ackermann {
+    pub fn new(limits: VelocityLimits, wheelbase: f32, max_steering_angle: f32) -> Self {
+        Self {
+            limits,
+            wheelbase,
+            max_steering_angle,
+            linear_velocity: 0.0,
+            steering_angle: 0.0,
+        }
+    }
+}
+
+impl MotionModel for Ackermann {
+    fn drive_towards(
+        &mut self,
+        pose: Pose2D,
+        target: Vector2,
+        distance_to_goal: f32,
+        dt: f32,
+    ) -> Pose2D {
+        let local = (target - pose.position).rotated(-pose.heading);
+
+        // Steer fully towards targets behind the vehicle, otherwise follow the pursuit arc
+        let desired_steering = if local.x < 0.0 {
+            local.y.signum() * self.max_steering_angle
+        } else {
+            (self.wheelbase * pursuit_curvature(pose, target)).atan()
+        }
+        .clamp(-self.max_steering_angle, self.max_steering_angle);
+
+        // The steering rate is bounded by the angular velocity limit
+        self.steering_angle = approach(
+            self.steering_angle,
+            desired_steering,
+            self.limits.max_angular_velocity * dt,
+        );
+
+        let curvature = self.steering_angle.tan() / self.wheelbase;
+        let mut v = self
+            .limits
+            .max_linear_velocity
+            .min(self.limits.stopping_speed(distance_to_goal));
+        if curvature.abs() > 1E-6 {
+            v = v.min(self.limits.max_angular_velocity / curvature.abs());
+        }
+        self.linear_velocity = approach(
+            self.linear_velocity,
+            v,
+            self.limits.max_linear_acceleration * dt,
+        );
+
+        integrate_unicycle(
+            pose,
+            self.linear_velocity,
+            self.linear_velocity * curvature,
+            dt,
+        )
+    }
+
+    fn min_turning_radius(&self) -> f32 {
+        self.wheelbase / self.max_steering_angle.tan()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn angles_wrap_to_half_open_range() {
+        assert!((wrap_angle(3.0 * PI / 2.0) + PI / 2.0).abs() < 1E-5);
+        assert!((wrap_angle(PI) + PI).abs() < 1E-5);
+        assert!((wrap_angle(-0.5) + 0.5).abs() < 1E-6);
+    }
+
+    #[test]
+    fn poses_compose_in_the_parent_frame() {
+        let base = Pose2D::new(Vector2::new(10.0, 0.0), FRAC_PI_2);
+        let composed = base.compose(Pose2D::new(Vector2::new(5.0, 0.0), FRAC_PI_2));
+        assert!((composed.position - Vector2::new(10.0, 5.0)).length() < 1E-5);
+        assert!((composed.heading + PI).abs() < 1E-5);
+    }
+
+    #[test]
+    fn diff_drive_respects_its_limits() {
+        let limits = VelocityLimits::new();
+        let mut vehicle = DifferentialDrive::new(limits);
+        let mut pose = Pose2D::new(Vector2::ZERO, 0.0);
+        let goal = Vector2::new(500.0, 0.0);
+        let dt = 0.1;
+
+        let mut previous = 0.0;
+        for _ in 0..200 {
+            let distance = goal.x - pose.position.x;
+            pose = vehicle.drive_towards(pose, goal, distance, dt);
+            let v = vehicle.linear_velocity;
+            assert!(v <= limits.max_linear_velocity + 1E-4);
+            assert!((v - previous).abs() <= limits.max_linear_acceleration * dt + 1E-4);
+            previous = v;
+        }
+
+        // It slows down to stop at the goal rather than overshooting it
+        assert!((pose.position.x - goal.x).abs() < 5.0);
+        assert!(vehicle.linear_velocity.abs() < 5.0);
+    }
+
+    #[test]
+    fn ackermann_turns_no_tighter_than_its_radius() {
+        let mut vehicle = Ackermann::new(VelocityLimits::new(), 30.0, 0.5);
+        let radius = vehicle.min_turning_radius();
+        assert!((radius - 30.0 / 0.5_f32.tan()).abs() < 1E-4);
+
+        // Steering hard towards a target behind it traces a circle of at least that radius
+        let mut pose = Pose2D::new(Vector2::ZERO, 0.0);
+        let target = Vector2::new(-10.0, 1.0);
+        let mut furthest: f32 = 0.0;
+        for _ in 0..200 {
+            pose = vehicle.drive_towards(pose, target, 1000.0, 0.1);
+            furthest = furthest.max(pose.position.y.abs());
+        }
+        assert!(furthest >= 2.0 * radius - 1.0);
+    }
+}
@@ -0,0 +1,119 @@
+use std::fs::File;
+use std::io::{BufWriter, Result as IoResult, Seek, Write};
+
+use crate::lidar::export::{Exporter, RobotRecord, RunRecord};
+use crate::lidar::motion::Pose2D;
+use crate::lidar::serializer::write_npy;
+use ndarray::{stack, Array2, Array3, ArrayView2, Axis};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+/// Exports each robot's run as a NumPy `.npz` archive, readable with `numpy.load`.
+///
+/// The archive holds `returns` as a frames × beams × channels array, the `poses`, `odometry` and
+/// `sensor_poses` as rows of time, x, y, heading, and the planned `path` as rows of x, y. With
+/// several sensors the per-sensor arrays are suffixed `_sensorK`. Units and frames are those of
+/// the JSON output: pixels and radians, with the poses in the world frame and the bearings
+/// relative to the sensor heading, or to the world x axis with `--world_bearings`.
+pub struct NpzExporter;
+
+impl NpzExporter {
+    pub fn new() -> Self {
+        Self
+    }
+
+    fn pose_array(times: &[f64], poses: &[Pose2D]) -> Array2<f64> {
+        // One row of time, x, y, heading per scan
+        let mut array = Array2::zeros((poses.len(), 4));
+        for (i, (t, p)) in times.iter().zip(poses.iter()).enumerate() {
+            array[[i, 0]] = *t;
+            array[[i, 1]] = p.position.x as f64;
+            array[[i, 2]] = p.position.y as f64;
+            array[[i, 3]] = p.heading as f64;
+        }
+        array
+    }
+
+    /// Stacks the scans into one frames × beams × channels array.
+    fn returns_array(returns: &[Array2<f64>], n_beams: usize, n_columns: usize) -> Array3<f64> {
+        if returns.is_empty() {
+            return Array3::zeros((0, n_beams, n_columns));
+        }
+        let views: Vec<ArrayView2<f64>> = returns.iter().map(|r| r.view()).collect();
+        stack(Axis(0), &views).unwrap()
+    }
+
+    fn write_archive<W: Write + Seek>(
+        &self,
+        writer: W,
+        robot: &RobotRecord,
+        n_columns: usize,
+    ) -> IoResult<()> {
+        let mut zip = ZipWriter::new(writer);
+
+        // Stored uncompressed, like numpy.savez
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+
+        let mut path = Array2::zeros((robot.path.len(), 2));
+        for (i, p) in robot.path.iter().enumerate() {
+            path[[i, 0]] = p.x as f64;
+            path[[i, 1]] = p.y as f64;
+        }
+
+        zip.start_file("poses.npy", options)?;
+        write_npy(&mut zip, Self::pose_array(robot.times, robot.poses).view())?;
+        zip.start_file("odometry.npy", options)?;
+        write_npy(
+            &mut zip,
+            Self::pose_array(robot.times, robot.odometry).view(),
+        )?;
+        zip.start_file("path.npy", options)?;
+        write_npy(&mut zip, path.view())?;
+
+        for (k, sensor) in robot.sensors.iter().enumerate() {
+            let suffix = if robot.sensors.len() > 1 {
+                format!("_sensor{}", k)
+            } else {
+                String::new()
+            };
+            let n_beams = sensor.config.beams.n_beams;
+            let returns = Self::returns_array(sensor.returns, n_beams, n_columns);
+
+            zip.start_file(format!("returns{}.npy", suffix), options)?;
+            write_npy(&mut zip, returns.view())?;
+            zip.start_file(format!("sensor_poses{}.npy", suffix), options)?;
+            write_npy(&mut zip, Self::pose_array(robot.times, sensor.poses).view())?;
+        }
+
+        zip.finish()?;
+        Ok(())
+    }
+}
+
+impl Exporter for NpzExporter {
+    fn export(&self, run: &RunRecord) -> IoResult<Vec<String>> {
+        let mut filenames = Vec::new();
+        for (r, robot) in run.robots.iter().enumerate() {
+            let filename = run.robot_filename("scans", r, "npz");
+            let file = File::create(&filename)?;
+            self.write_archive(BufWriter::new(file), robot, run.return_columns)?;
+            filenames.push(filename);
+        }
+        Ok(filenames)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn returns_keep_their_columns() {
+        let scans = vec![Array2::zeros((90, 5)), Array2::ones((90, 5))];
+        assert_eq!(
+            NpzExporter::returns_array(&scans, 90, 5).shape(),
+            [2, 90, 5]
+        );
+        assert_eq!(NpzExporter::returns_array(&[], 90, 5).shape(), [0, 90, 5]);
+    }
+}
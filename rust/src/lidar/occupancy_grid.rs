@@ -0,0 +1,229 @@
+use std::collections::VecDeque;
+
+use crate::lidar::geometry::point_in_polygon;
+use godot::prelude::*;
+
+/// Occupancy value of a cell that has not been observed.
+pub const UNKNOWN: i8 = -1;
+/// Occupancy value of a cell observed to be free.
+pub const FREE: i8 = 0;
+/// Occupancy value of a cell observed to be occupied.
+pub const OCCUPIED: i8 = 100;
+
+/// A 2D occupancy grid built up from the Lidar scans.
+///
+/// Cell `(i, j)` is centred on the world point `(i * resolution, j * resolution)`,
+/// matching the points of the A* grid.
+pub struct OccupancyGrid {
+    pub width: usize,
+    pub height: usize,
+    pub resolution: f32,
+    pub cells: Vec<i8>,
+}
+
+impl OccupancyGrid {
+    /// Creates a new grid with every cell unknown.
+    pub fn new(width: usize, height: usize, resolution: f32) -> Self {
+        Self {
+            width,
+            height,
+            resolution,
+            cells: vec![UNKNOWN; width * height],
+        }
+    }
+
+    /// Creates a fully observed grid of the ground truth, with the cells whose centres are inside
+    /// any of the polygons occupied and the rest free.
+    pub fn from_polygons(
+        width: usize,
+        height: usize,
+        resolution: f32,
+        polygons: &[Vec<Vector2>],
+    ) -> Self {
+        let mut grid = Self::new(width, height, resolution);
+        for j in 0..height {
+            for i in 0..width {
+                let centre = grid.cell_to_world(i, j);
+                let occupied = polygons
+                    .iter()
+                    .any(|polygon| point_in_polygon(centre, polygon));
+                let index = grid.index(i, j);
+                grid.cells[index] = if occupied { OCCUPIED } else { FREE };
+            }
+        }
+        grid
+    }
+
+    pub fn index(&self, i: usize, j: usize) -> usize {
+        i + self.width * j
+    }
+
+    pub fn get(&self, i: usize, j: usize) -> i8 {
+        self.cells[self.index(i, j)]
+    }
+
+    /// Converts a world position to the (possibly out of bounds) cell containing it.
+    pub fn world_to_cell(&self, point: Vector2) -> (i64, i64) {
+        (
+            (point.x / self.resolution).round() as i64,
+            (point.y / self.resolution).round() as i64,
+        )
+    }
+
+    pub fn cell_to_world(&self, i: usize, j: usize) -> Vector2 {
+        Vector2::new(i as f32 * self.resolution, j as f32 * self.resolution)
+    }
+
+    fn in_bounds(&self, i: i64, j: i64) -> bool {
+        i >= 0 && j >= 0 && (i as usize) < self.width && (j as usize) < self.height
+    }
+
+    /// Marks the cells along each beam as free and the end cell as occupied if the beam hit something.
+    pub fn integrate_scan(&mut self, origin: Vector2, endpoints: &[(Vector2, bool)]) {
+        let (x0, y0) = self.world_to_cell(origin);
+
+        for &(end, hit) in endpoints.iter() {
+            let (x1, y1) = self.world_to_cell(end);
+
+            // Bresenham line from the origin cell to the end cell
+            let dx = (x1 - x0).abs();
+            let dy = -(y1 - y0).abs();
+            let sx = if x0 < x1 { 1 } else { -1 };
+            let sy = if y0 < y1 { 1 } else { -1 };
+            let mut err = dx + dy;
+            let (mut x, mut y) = (x0, y0);
+
+            loop {
+                if !self.in_bounds(x, y) {
+                    break;
+                }
+                let idx = self.index(x as usize, y as usize);
+                if x == x1 && y == y1 {
+                    self.cells[idx] = if hit { OCCUPIED } else { FREE };
+                    break;
+                }
+                // Don't clear cells already seen as occupied by another beam
+                if self.cells[idx] != OCCUPIED {
+                    self.cells[idx] = FREE;
+                }
+
+                let e2 = 2 * err;
+                if e2 >= dy {
+                    err += dy;
+                    x += sx;
+                }
+                if e2 <= dx {
+                    err += dx;
+                    y += sy;
+                }
+            }
+        }
+    }
+
+    fn is_frontier(&self, i: usize, j: usize) -> bool {
+        if self.get(i, j) != FREE {
+            return false;
+        }
+        let (i, j) = (i as i64, j as i64);
+        [(1, 0), (-1, 0), (0, 1), (0, -1)]
+            .iter()
+            .any(|&(di, dj)| {
+                self.in_bounds(i + di, j + dj)
+                    && self.get((i + di) as usize, (j + dj) as usize) == UNKNOWN
+            })
+    }
+
+    /// Groups the frontier cells (free cells bordering unknown space) into 8-connected clusters,
+    /// dropping clusters smaller than `min_size`.
+    pub fn frontier_clusters(&self, min_size: usize) -> Vec<Vec<(usize, usize)>> {
+        let mut visited = vec![false; self.cells.len()];
+        let mut clusters = Vec::new();
+
+        for j in 0..self.height {
+            for i in 0..self.width {
+                if visited[self.index(i, j)] || !self.is_frontier(i, j) {
+                    continue;
+                }
+
+                let mut cluster = Vec::new();
+                let mut queue = VecDeque::from([(i, j)]);
+                visited[self.index(i, j)] = true;
+
+                while let Some((ci, cj)) = queue.pop_front() {
+                    cluster.push((ci, cj));
+                    for di in -1..=1_i64 {
+                        for dj in -1..=1_i64 {
+                            let (ni, nj) = (ci as i64 + di, cj as i64 + dj);
+                            if !self.in_bounds(ni, nj) {
+                                continue;
+                            }
+                            let (ni, nj) = (ni as usize, nj as usize);
+                            let idx = self.index(ni, nj);
+                            if !visited[idx] && self.is_frontier(ni, nj) {
+                                visited[idx] = true;
+                                queue.push_back((ni, nj));
+                            }
+                        }
+                    }
+                }
+
+                if cluster.len() >= min_size {
+                    clusters.push(cluster);
+                }
+            }
+        }
+
+        clusters
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scans_clear_the_beam_and_mark_the_hit() {
+        let mut grid = OccupancyGrid::new(10, 10, 10.0);
+        grid.integrate_scan(
+            Vector2::new(20.0, 50.0),
+            &[
+                (Vector2::new(70.0, 50.0), true),
+                (Vector2::new(20.0, 10.0), false),
+            ],
+        );
+
+        assert!((2..7).all(|i| grid.get(i, 5) == FREE));
+        assert_eq!(grid.get(7, 5), OCCUPIED);
+        assert!((1..=5).all(|j| grid.get(2, j) == FREE));
+        assert_eq!(grid.get(8, 5), UNKNOWN);
+    }
+
+    #[test]
+    fn frontiers_border_unknown_space() {
+        let mut grid = OccupancyGrid::new(10, 10, 10.0);
+        assert!(grid.frontier_clusters(1).is_empty());
+
+        grid.integrate_scan(
+            Vector2::new(20.0, 50.0),
+            &[(Vector2::new(70.0, 50.0), true)],
+        );
+        let clusters = grid.frontier_clusters(1);
+        assert_eq!(clusters.len(), 1);
+        assert_eq!(clusters[0].len(), 5);
+        assert!(grid.frontier_clusters(6).is_empty());
+    }
+
+    #[test]
+    fn ground_truth_is_fully_observed() {
+        let square = vec![
+            Vector2::new(15.0, 15.0),
+            Vector2::new(35.0, 15.0),
+            Vector2::new(35.0, 35.0),
+            Vector2::new(15.0, 35.0),
+        ];
+        let grid = OccupancyGrid::from_polygons(5, 5, 10.0, &[square]);
+        assert_eq!(grid.get(2, 2), OCCUPIED);
+        assert_eq!(grid.get(0, 0), FREE);
+        assert!(!grid.cells.contains(&UNKNOWN));
+    }
+}
@@ -0,0 +1,272 @@
+use std::f32::consts::PI;
+use std::str::FromStr;
+
+use crate::lidar::motion::{wrap_angle, Pose2D};
+use crate::lidar::rng;
+use godot::prelude::*;
+use rand_distr::Normal;
+
+/// Noise of the simulated wheel odometry, in the rotate, translate, rotate odometry motion model.
+///
+/// Each step's rotations and translation are perturbed by zero mean Gaussian noise with
+/// standard deviations proportional to the size of the motion.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OdometryNoise {
+    /// Rotation error per radian of rotation.
+    pub rot_rot: f32,
+    /// Rotation error (rad) per px of translation.
+    pub rot_trans: f32,
+    /// Translation error per px of translation.
+    pub trans_trans: f32,
+    /// Translation error (px) per radian of rotation.
+    pub trans_rot: f32,
+}
+
+impl OdometryNoise {
+    pub fn new(rot_rot: f32, rot_trans: f32, trans_trans: f32, trans_rot: f32) -> Self {
+        Self {
+            rot_rot,
+            rot_trans,
+            trans_trans,
+            trans_rot,
+        }
+    }
+
+    /// Covariance of the odometry's motion from `p0` to `p1`, as x, y and heading in the frame of
+    /// `p0`, linearizing the motion model about the measured motion.
+    pub fn covariance(&self, p0: Pose2D, p1: Pose2D) -> [[f32; 3]; 3] {
+        let (rot1, trans, rot2) = decompose_motion(p0, p1);
+        let variances = [
+            (self.rot_rot * rot1.abs() + self.rot_trans * trans.abs()).powi(2),
+            (self.trans_trans * trans.abs() + self.trans_rot * (rot1.abs() + rot2.abs())).powi(2),
+            (self.rot_rot * rot2.abs() + self.rot_trans * trans.abs()).powi(2),
+        ];
+
+        // Jacobian of (x, y, heading) = (trans cos rot1, trans sin rot1, rot1 + rot2)
+        let (sin, cos) = rot1.sin_cos();
+        let jacobian = [
+            [-trans * sin, cos, 0.0],
+            [trans * cos, sin, 0.0],
+            [1.0, 0.0, 1.0],
+        ];
+
+        let mut covariance = [[0.0; 3]; 3];
+        for (i, row) in covariance.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..3)
+                    .map(|k| jacobian[i][k] * variances[k] * jacobian[j][k])
+                    .sum();
+            }
+        }
+        covariance
+    }
+}
+
+impl FromStr for OdometryNoise {
+    type Err = String;
+
+    /// Parses noise given as `rot_rot,rot_trans,trans_trans,trans_rot`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values: Vec<f32> = s
+            .split(',')
+            .map(|v| v.trim().parse::<f32>())
+            .collect::<Result<_, _>>()
+            .map_err(|e| format!("Invalid odometry noise '{}': {}", s, e))?;
+
+        match values.as_slice() {
+            [rot_rot, rot_trans, trans_trans, trans_rot] => Ok(OdometryNoise::new(
+                *rot_rot,
+                *rot_trans,
+                *trans_trans,
+                *trans_rot,
+            )),
+            _ => Err(format!(
+                "Invalid odometry noise '{}', expected rot_rot,rot_trans,trans_trans,trans_rot",
+                s
+            )),
+        }
+    }
+}
+
+/// Decomposes the motion from `p0` to `p1` into a rotation, a translation and a second
+/// rotation, as `(rot1, trans, rot2)`.
+pub fn decompose_motion(p0: Pose2D, p1: Pose2D) -> (f32, f32, f32) {
+    let delta = p1.position - p0.position;
+    let mut trans = delta.length();
+    let mut rot1 = if trans > 1E-4 {
+        wrap_angle(delta.y.atan2(delta.x) - p0.heading)
+    } else {
+        0.0
+    };
+
+    // Driving backwards is a negative translation rather than a half turn
+    if rot1.abs() > PI / 2.0 {
+        rot1 = wrap_angle(rot1 + PI);
+        trans = -trans;
+    }
+    let rot2 = wrap_angle(p1.heading - p0.heading - rot1);
+
+    (rot1, trans, rot2)
+}
+
+/// Zero mean Gaussian noise with standard deviation `sigma`.
+fn gaussian(sigma: f32) -> f32 {
+    if sigma > 0.0 {
+        rng::sample(&Normal::new(0.0, sigma).unwrap())
+    } else {
+        0.0
+    }
+}
+
+/// Simulates wheel odometry along the ground-truth poses, starting from the first pose.
+///
+/// The motion between consecutive poses is decomposed into a rotation, a translation and a
+/// second rotation, each perturbed with `noise`, and the noisy motions are integrated, so the
+/// odometry drifts from the ground truth over the run.
+pub fn simulate_odometry(poses: &[Pose2D], noise: OdometryNoise) -> Vec<Pose2D> {
+    let mut odometry = Vec::with_capacity(poses.len());
+    let Some(first) = poses.first() else {
+        return odometry;
+    };
+
+    let mut current = *first;
+    odometry.push(current);
+
+    for pair in poses.windows(2) {
+        let (rot1_hat, trans_hat, rot2_hat) = decompose_motion(pair[0], pair[1]);
+
+        // The noise of each part scales with the true motion, as in the covariance
+        let rot1_sigma = noise.rot_rot * rot1_hat.abs() + noise.rot_trans * trans_hat.abs();
+        let trans_sigma = noise.trans_trans * trans_hat.abs()
+            + noise.trans_rot * (rot1_hat.abs() + rot2_hat.abs());
+        let rot2_sigma = noise.rot_rot * rot2_hat.abs() + noise.rot_trans * trans_hat.abs();
+        let rot1 = rot1_hat + gaussian(rot1_sigma);
+        let trans = trans_hat + gaussian(trans_sigma);
+        let rot2 = rot2_hat + gaussian(rot2_sigma);
+
+        let heading = current.heading + rot1;
+        current = Pose2D::new(
+            current.position + Vector2::from_angle(heading) * trans,
+            wrap_angle(heading + rot2),
+        );
+        odometry.push(current);
+    }
+
+    odometry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn poses() -> Vec<Pose2D> {
+        vec![
+            Pose2D::new(Vector2::new(0.0, 0.0), 0.0),
+            Pose2D::new(Vector2::new(10.0, 0.0), 0.3),
+            Pose2D::new(Vector2::new(15.0, 8.0), 1.2),
+            // Reversing
+            Pose2D::new(Vector2::new(13.0, 4.0), 1.1),
+        ]
+    }
+
+    #[test]
+    fn backwards_motion_is_a_negative_translation() {
+        let p0 = Pose2D::new(Vector2::ZERO, 0.0);
+        let (rot1, trans, rot2) = decompose_motion(p0, Pose2D::new(Vector2::new(-5.0, 0.0), 0.0));
+        assert!(rot1.abs() < 1E-6 && rot2.abs() < 1E-6);
+        assert!((trans + 5.0).abs() < 1E-6);
+    }
+
+    #[test]
+    fn noiseless_odometry_is_the_ground_truth() {
+        let poses = poses();
+        let odometry = simulate_odometry(&poses, OdometryNoise::new(0.0, 0.0, 0.0, 0.0));
+        assert_eq!(odometry.len(), poses.len());
+        for (odom, truth) in odometry.iter().zip(poses.iter()) {
+            assert!((odom.position - truth.position).length() < 1E-4);
+            assert!(wrap_angle(odom.heading - truth.heading).abs() < 1E-5);
+        }
+    }
+
+    #[test]
+    fn noisy_odometry_drifts_reproducibly() {
+        let poses = poses();
+        let noise = OdometryNoise::new(0.1, 0.01, 0.1, 1.0);
+        rng::seed(3);
+        let odometry = simulate_odometry(&poses, noise);
+        rng::seed(3);
+        assert_eq!(simulate_odometry(&poses, noise), odometry);
+
+        assert_eq!(odometry[0], poses[0]);
+        assert!((odometry[3].position - poses[3].position).length() > 1E-3);
+    }
+
+    #[test]
+    fn covariance_matches_the_sampled_motion() {
+        let p0 = Pose2D::new(Vector2::new(5.0, 5.0), 0.4);
+        let p1 = Pose2D::new(Vector2::new(25.0, 12.0), 0.9);
+        let noise = OdometryNoise::new(0.05, 0.001, 0.05, 1.0);
+        let covariance = noise.covariance(p0, p1);
+
+        // The spread of the simulated steps, in the frame of the first pose
+        rng::seed(5);
+        let n = 20000;
+        let steps: Vec<[f32; 3]> = (0..n)
+            .map(|_| {
+                let odometry = simulate_odometry(&[p0, p1], noise);
+                let delta = (odometry[1].position - p0.position).rotated(-p0.heading);
+                [delta.x, delta.y, odometry[1].heading - p0.heading]
+            })
+            .collect();
+        let mut mean = [0.0; 3];
+        for step in steps.iter() {
+            for k in 0..3 {
+                mean[k] += step[k] / n as f32;
+            }
+        }
+        for i in 0..3 {
+            for j in 0..3 {
+                let sampled: f32 = steps
+                    .iter()
+                    .map(|s| (s[i] - mean[i]) * (s[j] - mean[j]))
+                    .sum::<f32>()
+                    / n as f32;
+                let scale = (covariance[i][i] * covariance[j][j]).sqrt();
+                assert!(
+                    (sampled - covariance[i][j]).abs() < 0.1 * scale,
+                    "({}, {}): {} vs {}",
+                    i,
+                    j,
+                    sampled,
+                    covariance[i][j]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn turning_on_the_spot_only_adds_rotation_noise() {
+        let p0 = Pose2D::new(Vector2::new(5.0, 5.0), 0.0);
+        let p1 = Pose2D::new(Vector2::new(5.0, 5.0), 1.0);
+        let noise = OdometryNoise::new(0.1, 1.0, 0.1, 1.0);
+
+        // The translation is noisy, but the heading's spread only depends on the true rotation
+        rng::seed(6);
+        let n = 20000;
+        let variance = (0..n)
+            .map(|_| (simulate_odometry(&[p0, p1], noise)[1].heading - 1.0).powi(2))
+            .sum::<f32>()
+            / n as f32;
+        let expected = noise.covariance(p0, p1)[2][2];
+        assert!((expected - 0.01).abs() < 1E-6);
+        assert!((variance - expected).abs() < 0.1 * expected, "{}", variance);
+    }
+
+    #[test]
+    fn noise_parses_four_values() {
+        let noise: OdometryNoise = "0.1, 0.01,0.2,1".parse().unwrap();
+        assert_eq!(noise, OdometryNoise::new(0.1, 0.01, 0.2, 1.0));
+        assert!("0.1,0.2".parse::<OdometryNoise>().is_err());
+        assert!("a,b,c,d".parse::<OdometryNoise>().is_err());
+    }
+}
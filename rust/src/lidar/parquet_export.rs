@@ -0,0 +1,218 @@
+use std::fs::{create_dir_all, File};
+use std::io::Result as IoResult;
+use std::sync::Arc;
+
+use crate::lidar::export::{Exporter, RunRecord};
+use arrow::array::{ArrayRef, Float64Builder, Int64Builder, ListBuilder, UInt32Builder};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+
+/// A list column type, with nullable items as written by the Arrow list builders.
+fn list_of(item: DataType) -> DataType {
+    DataType::List(Arc::new(Field::new_list_field(item, true)))
+}
+
+/// Schema of the scan rows, shared by every iteration of the dataset.
+///
+/// There is one row per scan of each sensor on each robot:
+///
+/// | Column | Type | |
+/// |---|---|---|
+/// | `iteration` | uint32 | Index of the iteration |
+/// | `robot` | uint32 | Index of the robot |
+/// | `sensor` | uint32 | Index of the sensor on the robot |
+/// | `scan` | uint32 | Index of the scan in the iteration |
+/// | `time` | float64 | Time of the scan (s) |
+/// | `x`, `y`, `heading` | float64 | Ground-truth pose of the robot base (px, rad) |
+/// | `sensor_x`, `sensor_y`, `sensor_heading` | float64 | Ground-truth pose of the sensor |
+/// | `ranges` | list of float64 | Range of each beam, NaN where invalid |
+/// | `bearings` | list of float64 | Bearing of each beam (rad) |
+/// | `label_ids` | list of int64 | Id of the object each beam hit, null without `--labels` |
+/// | `label_classes` | list of int64 | Class of the object each beam hit, null without `--labels` |
+///
+/// The iteration is stored in every row, so each file stands on its own, as well as in the
+/// `iteration=N` directory it is written to.
+pub fn scan_schema() -> Schema {
+    Schema::new(vec![
+        Field::new("iteration", DataType::UInt32, false),
+        Field::new("robot", DataType::UInt32, false),
+        Field::new("sensor", DataType::UInt32, false),
+        Field::new("scan", DataType::UInt32, false),
+        Field::new("time", DataType::Float64, false),
+        Field::new("x", DataType::Float64, false),
+        Field::new("y", DataType::Float64, false),
+        Field::new("heading", DataType::Float64, false),
+        Field::new("sensor_x", DataType::Float64, false),
+        Field::new("sensor_y", DataType::Float64, false),
+        Field::new("sensor_heading", DataType::Float64, false),
+        Field::new("ranges", list_of(DataType::Float64), false),
+        Field::new("bearings", list_of(DataType::Float64), false),
+        Field::new("label_ids", list_of(DataType::Int64), true),
+        Field::new("label_classes", list_of(DataType::Int64), true),
+    ])
+}
+
+/// Exports every iteration into one Parquet dataset with the `scan_schema`, partitioned by
+/// iteration as `scans/iteration=N/part-0.parquet` under the output directory.
+pub struct ParquetExporter;
+
+impl ParquetExporter {
+    pub fn new() -> Self {
+        Self
+    }
+
+    fn scan_batch(&self, run: &RunRecord) -> Result<RecordBatch, arrow::error::ArrowError> {
+        let mut iteration = UInt32Builder::new();
+        let mut robot = UInt32Builder::new();
+        let mut sensor = UInt32Builder::new();
+        let mut scan = UInt32Builder::new();
+        let mut time = Float64Builder::new();
+        let mut base = [
+            Float64Builder::new(),
+            Float64Builder::new(),
+            Float64Builder::new(),
+        ];
+        let mut sensor_pose = [
+            Float64Builder::new(),
+            Float64Builder::new(),
+            Float64Builder::new(),
+        ];
+        let mut ranges = ListBuilder::new(Float64Builder::new());
+        let mut bearings = ListBuilder::new(Float64Builder::new());
+        let mut label_ids = ListBuilder::new(Int64Builder::new());
+        let mut label_classes = ListBuilder::new(Int64Builder::new());
+
+        for (r, record) in run.robots.iter().enumerate() {
+            for (k, sensor_record) in record.sensors.iter().enumerate() {
+                for (i, returns) in sensor_record.returns.iter().enumerate() {
+                    iteration.append_value(run.iteration);
+                    robot.append_value(r as u32);
+                    sensor.append_value(k as u32);
+                    scan.append_value(i as u32);
+                    time.append_value(record.times[i]);
+
+                    for (builder, pose) in [
+                        (&mut base, record.poses[i]),
+                        (&mut sensor_pose, sensor_record.poses[i]),
+                    ] {
+                        builder[0].append_value(pose.position.x as f64);
+                        builder[1].append_value(pose.position.y as f64);
+                        builder[2].append_value(pose.heading as f64);
+                    }
+
+                    ranges.values().append_slice(&returns.column(0).to_vec());
+                    ranges.append(true);
+                    bearings.values().append_slice(&returns.column(1).to_vec());
+                    bearings.append(true);
+
+                    match sensor_record.labels.get(i) {
+                        Some(labels) => {
+                            label_ids.values().append_slice(&labels.column(0).to_vec());
+                            label_ids.append(true);
+                            label_classes
+                                .values()
+                                .append_slice(&labels.column(1).to_vec());
+                            label_classes.append(true);
+                        }
+                        None => {
+                            label_ids.append_null();
+                            label_classes.append_null();
+                        }
+                    }
+                }
+            }
+        }
+
+        let [mut x, mut y, mut heading] = base;
+        let [mut sensor_x, mut sensor_y, mut sensor_heading] = sensor_pose;
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(iteration.finish()),
+            Arc::new(robot.finish()),
+            Arc::new(sensor.finish()),
+            Arc::new(scan.finish()),
+            Arc::new(time.finish()),
+            Arc::new(x.finish()),
+            Arc::new(y.finish()),
+            Arc::new(heading.finish()),
+            Arc::new(sensor_x.finish()),
+            Arc::new(sensor_y.finish()),
+            Arc::new(sensor_heading.finish()),
+            Arc::new(ranges.finish()),
+            Arc::new(bearings.finish()),
+            Arc::new(label_ids.finish()),
+            Arc::new(label_classes.finish()),
+        ];
+        RecordBatch::try_new(Arc::new(scan_schema()), columns)
+    }
+}
+
+impl Exporter for ParquetExporter {
+    fn export(&self, run: &RunRecord) -> IoResult<Vec<String>> {
+        let directory = format!("{}/scans/iteration={}", run.out_dir, run.iteration);
+        create_dir_all(&directory)?;
+
+        let batch = self.scan_batch(run).map_err(std::io::Error::other)?;
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build();
+
+        let filename = format!("{}/part-0.parquet", directory);
+        let file = File::create(&filename)?;
+        let mut writer = ArrowWriter::try_new(file, batch.schema(), Some(properties))
+            .map_err(std::io::Error::other)?;
+        writer.write(&batch).map_err(std::io::Error::other)?;
+        writer.close().map_err(std::io::Error::other)?;
+        Ok(vec![filename])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lidar::export::{RobotRecord, SensorRecord};
+    use crate::lidar::motion::Pose2D;
+    use crate::lidar::occupancy_grid::OccupancyGrid;
+    use crate::lidar::odometry::OdometryNoise;
+    use crate::lidar::sensor::SensorConfig;
+    use arrow::array::{Array, UInt32Array};
+    use godot::prelude::*;
+    use ndarray::Array2;
+
+    #[test]
+    fn every_row_has_its_iteration() {
+        let poses = [Pose2D::new(Vector2::ZERO, 0.0); 2];
+        let returns = [Array2::zeros((4, 2)), Array2::zeros((4, 2))];
+        let map = OccupancyGrid::new(1, 1, 1.0);
+        let run = RunRecord {
+            out_dir: "",
+            iteration: 7,
+            robots: vec![RobotRecord {
+                times: &[0.0, 0.1],
+                poses: &poses,
+                odometry: &poses,
+                path: &[],
+                loop_closures: Vec::new(),
+                sensors: vec![SensorRecord {
+                    config: SensorConfig::default_sensor(),
+                    returns: &returns,
+                    labels: &[],
+                    poses: &poses,
+                }],
+            }],
+            map: &map,
+            odometry_noise: OdometryNoise::new(0.0, 0.0, 0.0, 0.0),
+            return_columns: 2,
+        };
+
+        let batch = ParquetExporter::new().scan_batch(&run).unwrap();
+        let iteration = batch
+            .column_by_name("iteration")
+            .and_then(|c| c.as_any().downcast_ref::<UInt32Array>())
+            .unwrap();
+        assert_eq!(iteration.len(), 2);
+        assert!(iteration.iter().all(|i| i == Some(7)));
+    }
+}
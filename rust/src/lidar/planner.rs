@@ -0,0 +1,434 @@
+use std::collections::BinaryHeap;
+use std::str::FromStr;
+
+use crate::lidar::geometry::{densify_path, point_in_polygon, segment_is_free};
+use crate::lidar::rng;
+use godot::prelude::*;
+
+/// The algorithm used to generate the trajectory through the arena.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PlannerKind {
+    AStar,
+    RrtStar,
+    Prm,
+}
+
+impl FromStr for PlannerKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "astar" => Ok(PlannerKind::AStar),
+            "rrt_star" => Ok(PlannerKind::RrtStar),
+            "prm" => Ok(PlannerKind::Prm),
+            _ => Err(format!(
+                "Unknown planner '{}', expected one of astar, rrt_star, prm",
+                s
+            )),
+        }
+    }
+}
+
+/// Sampling-based planners (RRT*, PRM) operating directly on the polygon world.
+pub struct SamplingPlanner {
+    obstacles: Vec<Vec<Vector2>>,
+    min: Vector2,
+    max: Vector2,
+    pub clearance: f32,
+    pub rrt_iterations: usize,
+    pub rrt_step: f32,
+    pub rrt_goal_bias: f32,
+    pub rrt_rewire_radius: f32,
+    pub prm_samples: usize,
+    pub prm_neighbours: usize,
+    pub prm_radius: f32,
+}
+
+struct TreeNode {
+    point: Vector2,
+    parent: Option<usize>,
+    cost: f32,
+}
+
+// Min-heap entry for Dijkstra over the roadmap
+#[derive(PartialEq)]
+struct QueueEntry {
+    cost: f32,
+    node: usize,
+}
+
+impl Eq for QueueEntry {}
+
+impl Ord for QueueEntry {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        other.cost.total_cmp(&self.cost)
+    }
+}
+
+impl PartialOrd for QueueEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl SamplingPlanner {
+    pub fn new(obstacles: Vec<Vec<Vector2>>, min: Vector2, max: Vector2) -> Self {
+        Self {
+            obstacles,
+            min,
+            max,
+            clearance: 5.0,
+            rrt_iterations: 4000,
+            rrt_step: 40.0,
+            rrt_goal_bias: 0.05,
+            rrt_rewire_radius: 80.0,
+            prm_samples: 600,
+            prm_neighbours: 10,
+            prm_radius: 150.0,
+        }
+    }
+
+    fn sample_point(&self) -> Vector2 {
+        Vector2::new(
+            rand_range(self.min.x, self.max.x),
+            rand_range(self.min.y, self.max.y),
+        )
+    }
+
+    fn is_free(&self, point: Vector2) -> bool {
+        !self
+            .obstacles
+            .iter()
+            .any(|polygon| point_in_polygon(point, polygon))
+    }
+
+    fn edge_is_free(&self, a: Vector2, b: Vector2) -> bool {
+        segment_is_free(a, b, &self.obstacles, self.clearance)
+    }
+
+    /// Plans a path with RRT*, returning an empty path if the goal was not reached.
+    pub fn rrt_star(&self, start: Vector2, goal: Vector2) -> Vec<Vector2> {
+        let mut tree = vec![TreeNode {
+            point: start,
+            parent: None,
+            cost: 0.0,
+        }];
+        let mut goal_node: Option<usize> = None;
+
+        for _ in 0..self.rrt_iterations {
+            let sample = if rng::random::<f32>() < self.rrt_goal_bias {
+                goal
+            } else {
+                self.sample_point()
+            };
+
+            let nearest = tree
+                .iter()
+                .enumerate()
+                .min_by(|(_, a), (_, b)| {
+                    a.point
+                        .distance_squared_to(sample)
+                        .total_cmp(&b.point.distance_squared_to(sample))
+                })
+                .map(|(i, _)| i)
+                .unwrap();
+
+            // Steer from the nearest node towards the sample
+            let from = tree[nearest].point;
+            let distance = from.distance_to(sample);
+            let new_point = if distance > self.rrt_step {
+                from + (sample - from) * (self.rrt_step / distance)
+            } else {
+                sample
+            };
+
+            if !self.is_free(new_point) || !self.edge_is_free(from, new_point) {
+                continue;
+            }
+
+            let neighbours: Vec<usize> = tree
+                .iter()
+                .enumerate()
+                .filter(|(_, n)| n.point.distance_to(new_point) < self.rrt_rewire_radius)
+                .map(|(i, _)| i)
+                .collect();
+
+            // Choose the lowest cost parent among the neighbours
+            let mut parent = nearest;
+            let mut cost = tree[nearest].cost + from.distance_to(new_point);
+            for &n in neighbours.iter() {
+                let candidate = tree[n].cost + tree[n].point.distance_to(new_point);
+                if candidate < cost && self.edge_is_free(tree[n].point, new_point) {
+                    parent = n;
+                    cost = candidate;
+                }
+            }
+
+            let new_idx = tree.len();
+            tree.push(TreeNode {
+                point: new_point,
+                parent: Some(parent),
+                cost,
+            });
+
+            // Rewire neighbours through the new node if that is cheaper
+            for &n in neighbours.iter() {
+                let candidate = cost + new_point.distance_to(tree[n].point);
+                if candidate < tree[n].cost && self.edge_is_free(new_point, tree[n].point) {
+                    tree[n].parent = Some(new_idx);
+                    tree[n].cost = candidate;
+                }
+            }
+
+            if new_point.distance_to(goal) < self.rrt_step && self.edge_is_free(new_point, goal) {
+                let goal_cost = cost + new_point.distance_to(goal);
+                let improves = match goal_node {
+                    Some(g) => goal_cost < tree[g].cost,
+                    None => true,
+                };
+                if improves {
+                    tree.push(TreeNode {
+                        point: goal,
+                        parent: Some(new_idx),
+                        cost: goal_cost,
+                    });
+                    goal_node = Some(tree.len() - 1);
+                }
+            }
+        }
+
+        match goal_node {
+            Some(g) => {
+                let mut path = Vec::new();
+                let mut current = Some(g);
+                while let Some(idx) = current {
+                    path.push(tree[idx].point);
+                    current = tree[idx].parent;
+                }
+                path.reverse();
+                path
+            }
+            None => Vec::new(),
+        }
+    }
+
+    /// Plans a path over a probabilistic roadmap, returning an empty path if start and goal are not connected.
+    pub fn prm(&self, start: Vector2, goal: Vector2) -> Vec<Vector2> {
+        // Sampling gives up when little of the arena is free, planning over the nodes found so far
+        let mut nodes = vec![start, goal];
+        let mut attempts = 0;
+        while nodes.len() < self.prm_samples + 2 && attempts < 10 * self.prm_samples {
+            attempts += 1;
+            let sample = self.sample_point();
+            if self.is_free(sample) {
+                nodes.push(sample);
+            }
+        }
+
+        // Connect each node to its nearest visible neighbours
+        let mut edges: Vec<Vec<(usize, f32)>> = vec![Vec::new(); nodes.len()];
+        for i in 0..nodes.len() {
+            let mut candidates: Vec<(usize, f32)> = (0..nodes.len())
+                .filter(|&j| j != i)
+                .map(|j| (j, nodes[i].distance_to(nodes[j])))
+                .filter(|&(_, d)| d < self.prm_radius)
+                .collect();
+            candidates.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+            for &(j, d) in candidates.iter().take(self.prm_neighbours) {
+                if edges[i].iter().any(|&(k, _)| k == j) {
+                    continue;
+                }
+                if self.edge_is_free(nodes[i], nodes[j]) {
+                    edges[i].push((j, d));
+                    edges[j].push((i, d));
+                }
+            }
+        }
+
+        // Dijkstra from the start (node 0) to the goal (node 1)
+        let mut dist = vec![f32::INFINITY; nodes.len()];
+        let mut prev: Vec<Option<usize>> = vec![None; nodes.len()];
+        let mut queue = BinaryHeap::new();
+        dist[0] = 0.0;
+        queue.push(QueueEntry { cost: 0.0, node: 0 });
+
+        while let Some(QueueEntry { cost, node }) = queue.pop() {
+            if node == 1 {
+                break;
+            }
+            if cost > dist[node] {
+                continue;
+            }
+            for &(next, d) in edges[node].iter() {
+                let candidate = cost + d;
+                if candidate < dist[next] {
+                    dist[next] = candidate;
+                    prev[next] = Some(node);
+                    queue.push(QueueEntry {
+                        cost: candidate,
+                        node: next,
+                    });
+                }
+            }
+        }
+
+        if dist[1].is_infinite() {
+            return Vec::new();
+        }
+
+        let mut path = Vec::new();
+        let mut current = Some(1);
+        while let Some(idx) = current {
+            path.push(nodes[idx]);
+            current = prev[idx];
+        }
+        path.reverse();
+        path
+    }
+}
+
+/// Makes a path followable by a vehicle with a minimum turning radius.
+///
+/// The path is first shortcut between mutually visible points, then every corner is replaced by an
+/// arc of `radius` tangent to both segments. Where the segments are too short for a full arc the
+/// largest arc that fits is used instead. The result is resampled every `spacing`.
+pub fn smooth_path(
+    path: &[Vector2],
+    radius: f32,
+    obstacles: &[Vec<Vector2>],
+    spacing: f32,
+) -> Vec<Vector2> {
+    if path.len() < 3 {
+        return path.to_vec();
+    }
+
+    // Greedily skip ahead to the furthest point that can be reached in a straight line
+    let mut corners = vec![path[0]];
+    let mut i = 0;
+    while i < path.len() - 1 {
+        let mut j = i + 1;
+        while j + 1 < path.len() && segment_is_free(path[i], path[j + 1], obstacles, 0.0) {
+            j += 1;
+        }
+        corners.push(path[j]);
+        i = j;
+    }
+
+    let mut smoothed = vec![corners[0]];
+    for k in 1..corners.len() - 1 {
+        let (prev, corner, next) = (corners[k - 1], corners[k], corners[k + 1]);
+        let d1 = (corner - prev).normalized();
+        let d2 = (next - corner).normalized();
+        let turn = d1.dot(d2).clamp(-1.0, 1.0).acos();
+        if turn < 1E-3 {
+            smoothed.push(corner);
+            continue;
+        }
+
+        // Segments between two corners are shared, so each corner may only use half of them
+        let available_in = corner.distance_to(prev) / if k == 1 { 1.0 } else { 2.0 };
+        let available_out = corner.distance_to(next) / if k == corners.len() - 2 { 1.0 } else { 2.0 };
+        let half_tan = (turn / 2.0).tan();
+        let tangent = (radius * half_tan).min(available_in).min(available_out);
+        let arc_radius = tangent / half_tan;
+
+        let side = d1.cross(d2).signum();
+        let arc_start = corner - d1 * tangent;
+        let centre = arc_start + d1.rotated(side * std::f32::consts::FRAC_PI_2) * arc_radius;
+
+        let n_steps = ((arc_radius * turn / spacing).ceil() as usize).max(1);
+        for step in 0..=n_steps {
+            let sweep = side * turn * step as f32 / n_steps as f32;
+            smoothed.push(centre + (arc_start - centre).rotated(sweep));
+        }
+    }
+    smoothed.push(corners[corners.len() - 1]);
+
+    densify_path(&smoothed, spacing)
+}
+
+// Helper function for generating random float range
+fn rand_range(min: f32, max: f32) -> f32 {
+    rng::random::<f32>() * (max - min) + min
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An arena with a wall across the middle, open at the bottom.
+    fn planner() -> SamplingPlanner {
+        let wall = vec![
+            Vector2::new(240.0, 0.0),
+            Vector2::new(260.0, 0.0),
+            Vector2::new(260.0, 350.0),
+            Vector2::new(240.0, 350.0),
+        ];
+        SamplingPlanner::new(vec![wall], Vector2::ZERO, Vector2::new(500.0, 500.0))
+    }
+
+    fn assert_valid(planner: &SamplingPlanner, path: &[Vector2], start: Vector2, goal: Vector2) {
+        assert_eq!(path.first(), Some(&start));
+        assert_eq!(path.last(), Some(&goal));
+        for w in path.windows(2) {
+            assert!(segment_is_free(w[0], w[1], &planner.obstacles, 0.0));
+        }
+    }
+
+    #[test]
+    fn sampling_planners_go_around_obstacles() {
+        let planner = planner();
+        let (start, goal) = (Vector2::new(50.0, 50.0), Vector2::new(450.0, 50.0));
+
+        rng::seed(1);
+        assert_valid(&planner, &planner.rrt_star(start, goal), start, goal);
+        rng::seed(1);
+        assert_valid(&planner, &planner.prm(start, goal), start, goal);
+    }
+
+    #[test]
+    fn roadmaps_are_built_when_little_is_free() {
+        // Only a narrow corridor along the top of the arena is free
+        let block = vec![
+            Vector2::new(-10.0, 20.0),
+            Vector2::new(510.0, 20.0),
+            Vector2::new(510.0, 510.0),
+            Vector2::new(-10.0, 510.0),
+        ];
+        let mut planner =
+            SamplingPlanner::new(vec![block], Vector2::ZERO, Vector2::new(500.0, 500.0));
+        planner.clearance = 0.0;
+        let (start, goal) = (Vector2::new(50.0, 10.0), Vector2::new(450.0, 10.0));
+        rng::seed(1);
+        assert_valid(&planner, &planner.prm(start, goal), start, goal);
+
+        // With nothing free the start and goal are not connected
+        let blocked = SamplingPlanner::new(
+            vec![vec![
+                Vector2::new(-10.0, -10.0),
+                Vector2::new(510.0, -10.0),
+                Vector2::new(510.0, 510.0),
+                Vector2::new(-10.0, 510.0),
+            ]],
+            Vector2::ZERO,
+            Vector2::new(500.0, 500.0),
+        );
+        assert!(blocked.prm(start, goal).is_empty());
+    }
+
+    #[test]
+    fn smoothed_paths_avoid_sharp_corners() {
+        let path = [
+            Vector2::new(0.0, 0.0),
+            Vector2::new(100.0, 0.0),
+            Vector2::new(100.0, 100.0),
+        ];
+        let smoothed = smooth_path(&path, 30.0, &[], 5.0);
+        assert_eq!(smoothed.first(), path.first());
+        assert_eq!(smoothed.last(), path.last());
+        assert!(smoothed
+            .iter()
+            .all(|p| p.distance_to(Vector2::new(100.0, 0.0)) > 10.0));
+    }
+}
@@ -0,0 +1,175 @@
+use std::fs::File;
+use std::io::{BufWriter, Result as IoResult, Write};
+
+use crate::lidar::export::{Exporter, MetricPose, RunRecord};
+use crate::lidar::motion::Pose2D;
+
+/// Exports the ground-truth and odometry trajectories in the TUM format read by `evo`, one line
+/// of `timestamp x y z qx qy qz qw` per scan.
+///
+/// Poses are in metres in the right-handed frame of the other exports, with z up.
+pub struct TumExporter {
+    /// Scale from the simulation's pixels to metres.
+    pub metres_per_pixel: f64,
+}
+
+impl TumExporter {
+    pub fn new(metres_per_pixel: f64) -> Self {
+        Self { metres_per_pixel }
+    }
+
+    fn write_trajectory(
+        &self,
+        out: &mut impl Write,
+        times: &[f64],
+        poses: &[Pose2D],
+    ) -> IoResult<()> {
+        for (t, pose) in times.iter().zip(poses.iter()) {
+            let pose = MetricPose::from_pose(*pose, self.metres_per_pixel);
+            writeln!(
+                out,
+                "{:.6} {:.6} {:.6} 0 0 0 {:.9} {:.9}",
+                t,
+                pose.x,
+                pose.y,
+                (pose.yaw / 2.0).sin(),
+                (pose.yaw / 2.0).cos()
+            )?;
+        }
+        out.flush()
+    }
+}
+
+impl Exporter for TumExporter {
+    fn export(&self, run: &RunRecord) -> IoResult<Vec<String>> {
+        let mut filenames = Vec::new();
+        for (r, robot) in run.robots.iter().enumerate() {
+            for (kind, poses) in [("poses", robot.poses), ("odometry", robot.odometry)] {
+                let filename = run.robot_filename(kind, r, "tum");
+                let file = File::create(&filename)?;
+                self.write_trajectory(&mut BufWriter::new(file), robot.times, poses)?;
+                filenames.push(filename);
+            }
+        }
+        Ok(filenames)
+    }
+}
+
+/// Exports the ground-truth and odometry trajectories in the KITTI odometry format, one line per
+/// scan of the first three rows of the pose matrix, with the scan times in a separate file.
+///
+/// As in the KITTI ground truth, poses are relative to the first ground-truth pose. They are in
+/// metres in the right-handed frame of the other exports, moving in the x-y plane, rather than in
+/// KITTI's camera frame.
+pub struct KittiExporter {
+    /// Scale from the simulation's pixels to metres.
+    pub metres_per_pixel: f64,
+}
+
+impl KittiExporter {
+    pub fn new(metres_per_pixel: f64) -> Self {
+        Self { metres_per_pixel }
+    }
+
+    fn write_trajectory(
+        &self,
+        out: &mut impl Write,
+        origin: Pose2D,
+        poses: &[Pose2D],
+    ) -> IoResult<()> {
+        let origin = MetricPose::from_pose(origin, self.metres_per_pixel);
+        for pose in poses.iter() {
+            let pose = MetricPose::from_pose(*pose, self.metres_per_pixel).relative_to(origin);
+            let (sin, cos) = pose.yaw.sin_cos();
+            writeln!(
+                out,
+                "{:.9} {:.9} 0 {:.9} {:.9} {:.9} 0 {:.9} 0 0 1 0",
+                cos, -sin, pose.x, sin, cos, pose.y
+            )?;
+        }
+        out.flush()
+    }
+}
+
+impl Exporter for KittiExporter {
+    fn export(&self, run: &RunRecord) -> IoResult<Vec<String>> {
+        let mut filenames = Vec::new();
+        for (r, robot) in run.robots.iter().enumerate() {
+            let Some(origin) = robot.poses.first() else {
+                continue;
+            };
+
+            // Both relative to the ground truth, so the odometry drift is kept
+            for (kind, poses) in [("poses", robot.poses), ("odometry", robot.odometry)] {
+                let filename = run.robot_filename(&format!("{}_kitti", kind), r, "txt");
+                let file = File::create(&filename)?;
+                self.write_trajectory(&mut BufWriter::new(file), *origin, poses)?;
+                filenames.push(filename);
+            }
+
+            let filename = run.robot_filename("times_kitti", r, "txt");
+            let mut out = BufWriter::new(File::create(&filename)?);
+            for t in robot.times.iter() {
+                writeln!(out, "{:.6}", t)?;
+            }
+            out.flush()?;
+            filenames.push(filename);
+        }
+        Ok(filenames)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use godot::prelude::*;
+    use std::f32::consts::FRAC_PI_2;
+
+    fn values(line: &str) -> Vec<f64> {
+        line.split_whitespace()
+            .map(|v| v.parse().unwrap())
+            .collect()
+    }
+
+    fn assert_close(actual: &[f64], expected: &[f64]) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected.iter()) {
+            assert!((a - e).abs() < 1E-6, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn tum_lines_are_mirrored_into_metres() {
+        // Facing up the screen, which is a left turn from the x axis in a right-handed frame
+        let pose = Pose2D::new(Vector2::new(100.0, 50.0), -FRAC_PI_2);
+        let mut out = Vec::new();
+        TumExporter::new(0.01)
+            .write_trajectory(&mut out, &[1.5], &[pose])
+            .unwrap();
+
+        let half = std::f64::consts::FRAC_1_SQRT_2;
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out.lines().count(), 1);
+        assert_close(&values(&out), &[1.5, 1.0, -0.5, 0.0, 0.0, 0.0, half, half]);
+    }
+
+    #[test]
+    fn kitti_rows_are_relative_to_the_origin() {
+        let origin = Pose2D::new(Vector2::new(100.0, 100.0), 0.0);
+        let pose = Pose2D::new(Vector2::new(200.0, 50.0), -FRAC_PI_2);
+        let mut out = Vec::new();
+        KittiExporter::new(0.01)
+            .write_trajectory(&mut out, origin, &[origin, pose])
+            .unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 2);
+        let identity = [1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0];
+        assert_close(&values(lines[0]), &identity);
+
+        // 1 m forward and 0.5 m to the left, turned a quarter turn to the left
+        let rotated = [0.0, -1.0, 0.0, 1.0, 1.0, 0.0, 0.0, 0.5, 0.0, 0.0, 1.0, 0.0];
+        assert_close(&values(lines[1]), &rotated);
+    }
+}
@@ -0,0 +1,127 @@
+use crate::lidar::motion::Pose2D;
+use godot::prelude::*;
+
+/// A pure pursuit path-following controller, choosing the point on the path to steer towards.
+pub struct PurePursuit {
+    /// Distance ahead along the path of the point being steered towards.
+    pub lookahead: f32,
+    /// Distance from the end of the path at which the goal counts as reached.
+    pub goal_tolerance: f32,
+    /// Number of path points ahead of the current progress searched for the closest point.
+    pub search_window: usize,
+    progress: usize,
+}
+
+impl PurePursuit {
+    pub fn new() -> Self {
+        Self {
+            lookahead: 30.0,
+            goal_tolerance: 2.0,
+            search_window: 20,
+            progress: 0,
+        }
+    }
+
+    /// Returns the lookahead point to steer towards from `pose`, or `None` once the end of the path is reached.
+    pub fn target(&mut self, pose: Pose2D, path: &[Vector2]) -> Option<Vector2> {
+        let goal = *path.last()?;
+        let distance_to_goal = pose.position.distance_to(goal);
+
+        // Advance to the closest point (the furthest along on ties), only looking a short way
+        // ahead so that revisited parts of the path are not skipped
+        let end = (self.progress + self.search_window).min(path.len());
+        self.progress = (self.progress..end)
+            .min_by(|&a, &b| {
+                pose.position
+                    .distance_squared_to(path[a])
+                    .total_cmp(&pose.position.distance_squared_to(path[b]))
+                    .then(b.cmp(&a))
+            })
+            .unwrap_or(self.progress);
+
+        if self.progress >= path.len() - 1 {
+            // Vehicles that can't turn on the spot may pass beside the goal rather than through it
+            let goal_behind = (goal - pose.position).rotated(-pose.heading).x < 0.0;
+            if distance_to_goal < self.goal_tolerance
+                || (goal_behind && distance_to_goal < self.lookahead)
+            {
+                return None;
+            }
+        }
+
+        let target = path[self.progress..]
+            .iter()
+            .find(|p| p.distance_to(pose.position) >= self.lookahead)
+            .copied()
+            .unwrap_or(goal);
+
+        Some(target)
+    }
+
+    /// Distance left to travel along the path from `pose`, via the point of the current progress.
+    ///
+    /// Unlike the straight-line distance to the goal, this is not zero at the start of a path that
+    /// ends where it began.
+    pub fn remaining_distance(&self, pose: Pose2D, path: &[Vector2]) -> f32 {
+        let Some(&next) = path.get(self.progress) else {
+            return 0.0;
+        };
+        let along: f32 = path[self.progress..]
+            .windows(2)
+            .map(|w| w[0].distance_to(w[1]))
+            .sum();
+        pose.position.distance_to(next) + along
+    }
+}
+
+/// Curvature of the arc from `pose` through `target`, positive when turning towards +y in the robot frame.
+pub fn pursuit_curvature(pose: Pose2D, target: Vector2) -> f32 {
+    let local = (target - pose.position).rotated(-pose.heading);
+    2.0 * local.y / local.length_squared().max(1E-6)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lidar::geometry::densify_path;
+    use crate::lidar::motion::{DifferentialDrive, Holonomic, MotionModel, VelocityLimits};
+
+    /// A square loop that ends where it starts, like the loop trajectories.
+    fn loop_path() -> Vec<Vector2> {
+        let corners = [
+            Vector2::new(100.0, 100.0),
+            Vector2::new(300.0, 100.0),
+            Vector2::new(300.0, 300.0),
+            Vector2::new(100.0, 300.0),
+            Vector2::new(100.0, 100.0),
+        ];
+        densify_path(&corners, 5.0)
+    }
+
+    #[test]
+    fn remaining_distance_of_a_loop_is_its_length() {
+        let path = loop_path();
+        let controller = PurePursuit::new();
+        let pose = Pose2D::new(path[0], 0.0);
+        assert!((controller.remaining_distance(pose, &path) - 800.0).abs() < 1E-3);
+    }
+
+    #[test]
+    fn vehicles_complete_a_loop() {
+        let path = loop_path();
+        let mut diff_drive = DifferentialDrive::new(VelocityLimits::new());
+        let mut holonomic = Holonomic::new(VelocityLimits::new(), 0.0);
+        for vehicle in [&mut diff_drive as &mut dyn MotionModel, &mut holonomic] {
+            let mut controller = PurePursuit::new();
+            let mut pose = Pose2D::new(path[0], 0.0);
+            let mut steps = 0;
+            while let Some(target) = controller.target(pose, &path) {
+                let distance = controller.remaining_distance(pose, &path);
+                pose = vehicle.drive_towards(pose, target, distance, 0.1);
+                steps += 1;
+                assert!(steps < 1000, "the loop was not completed");
+            }
+            assert!(pose.position.distance_to(path[0]) < PurePursuit::new().lookahead);
+        }
+    }
+}
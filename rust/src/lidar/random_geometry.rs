@@ -1,11 +1,39 @@
+use crate::lidar::labels::ObjectClass;
+use crate::lidar::rng;
 use godot::classes::{INode2D, Node2D, Polygon2D};
 use godot::prelude::*;
 
+/// Settings of the random geometry.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GeometryConfig {
+    pub n_shapes: u32,
+    /// Range of the square sides and circle radii (px).
+    pub min_size: f32,
+    pub max_size: f32,
+    /// Fraction of the shapes that are squares, the rest being circles.
+    pub square_fraction: f32,
+    pub wall_thickness: f32,
+}
+
+impl GeometryConfig {
+    pub fn new() -> Self {
+        Self {
+            n_shapes: 100,
+            min_size: 10.0,
+            max_size: 100.0,
+            square_fraction: 0.5,
+            wall_thickness: 10.0,
+        }
+    }
+}
+
 #[derive(GodotClass)]
 #[class(base=Node2D)]
 pub struct RandomGeometryGenerator {
     base: Base<Node2D>,
+    config: GeometryConfig,
     pub polygons: Vec<Gd<Polygon2D>>,
+    pub classes: Vec<ObjectClass>, // Class of each polygon, for the beam labels
 }
 
 #[godot_api]
@@ -15,27 +43,31 @@ impl INode2D for RandomGeometryGenerator {
 
         Self {
             base,
+            config: GeometryConfig::new(),
             polygons: Vec::new(),
+            classes: Vec::new(),
         }
     }
 
     fn ready(&mut self) {
-        const NUM_SHAPES: i32 = 100;
         let screen_width = 1024.0;
         let screen_height = 1024.0;
 
         let mut polygons = Vec::new();
+        let mut classes = Vec::new();
 
-        for _ in 0..NUM_SHAPES {
-            if rand::random::<f32>() < 0.5 {
+        for _ in 0..self.config.n_shapes {
+            if rng::random::<f32>() < self.config.square_fraction {
                 // godot_print!("Generating square!");
                 let square = self.generate_random_square(screen_width, screen_height);
                 polygons.push(square.clone());
+                classes.push(ObjectClass::Box);
                 self.base_mut().add_child(square);
             } else {
                 // godot_print!("Generating circle!");
                 let circle = self.generate_random_circle(screen_width, screen_height);
                 polygons.push(circle.clone());
+                classes.push(ObjectClass::Circle);
                 self.base_mut().add_child(circle);
             }
         }
@@ -48,7 +80,7 @@ impl INode2D for RandomGeometryGenerator {
 
         let arena_width = 1024.0; // Example arena size
         let arena_height = 1024.0; // Example arena size
-        let wall_thickness = 10.0; // Example wall thickness
+        let wall_thickness = self.config.wall_thickness;
 
         // Generate walls
         let walls = self.create_arena_walls(arena_width, arena_height, wall_thickness);
@@ -56,18 +88,22 @@ impl INode2D for RandomGeometryGenerator {
         // Add walls to the scene
         for wall in walls {
             polygons.push(wall.clone());
+            classes.push(ObjectClass::Wall);
             self.base_mut().add_child(wall);
         }
 
         self.polygons = polygons;
+        self.classes = classes;
     }
 }
 
 impl RandomGeometryGenerator {
-    pub fn new() -> Gd<Self> {
+    pub fn new(config: GeometryConfig) -> Gd<Self> {
         Gd::from_init_fn(|base| Self {
             base,
+            config,
             polygons: Vec::new(),
+            classes: Vec::new(),
         })
     }
 
@@ -75,7 +111,7 @@ impl RandomGeometryGenerator {
         let mut polygon = Polygon2D::new_alloc();
 
         // Define the size of the square
-        let size = rand_range(10.0, 100.0);
+        let size = rand_range(self.config.min_size, self.config.max_size);
 
         // Define the vertices for the square
         let mut vertices = vec![
@@ -114,7 +150,7 @@ impl RandomGeometryGenerator {
     fn generate_random_circle(&mut self, screen_width: f32, screen_height: f32) -> Gd<Polygon2D> {
         let mut circle = Polygon2D::new_alloc();
 
-        let radius = rand_range(10.0, 100.0);
+        let radius = rand_range(self.config.min_size, self.config.max_size);
         let mut polygon = self.create_circle_polygon(radius);
 
         let translation = Vector2::new(
@@ -204,5 +240,5 @@ impl RandomGeometryGenerator {
 
 // Helper function for generating random float range
 fn rand_range(min: f32, max: f32) -> f32 {
-    rand::random::<f32>() * (max - min) + min
+    rng::random::<f32>() * (max - min) + min
 }
@@ -0,0 +1,156 @@
+use rand::distributions::Uniform;
+
+use crate::lidar::rng;
+
+/// Whether a setting is given as a range or a set of choices, to be drawn on every iteration.
+///
+/// Any value of a list, such as the beam count of a sensor, can be `min..max`, drawn uniformly,
+/// or `a|b|c`, one of the choices. A range with whole-number bounds draws whole numbers.
+pub fn is_randomized(value: &str) -> bool {
+    value.contains("..") || value.contains('|')
+}
+
+/// Checks the ranges and choices of a setting.
+pub fn check(value: &str) -> Result<(), String> {
+    for item in value.split([',', ';']) {
+        for choice in item.split('|') {
+            if choice.is_empty() {
+                return Err("empty choice".to_string());
+            }
+            if let Some((min, max)) = choice.split_once("..") {
+                let (min, max) = match (min.parse::<f64>(), max.parse::<f64>()) {
+                    (Ok(min), Ok(max)) => (min, max),
+                    _ => return Err(format!("invalid range {}", choice)),
+                };
+                if min > max {
+                    return Err(format!("range {} is empty", choice));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Draws a value for a randomized setting from the shared generator, leaving its fixed parts.
+pub fn draw(value: &str) -> String {
+    value
+        .split_inclusive([',', ';'])
+        .map(|item| {
+            let separator = item.trim_start_matches(|c| c != ',' && c != ';');
+            let item = &item[..item.len() - separator.len()];
+            format!("{}{}", draw_item(item), separator)
+        })
+        .collect()
+}
+
+/// Every value a draw can end up at, with each range at either bound, for each combination of
+/// the ranges and choices of a setting.
+pub fn extremes(value: &str) -> Vec<String> {
+    let mut values = vec![String::new()];
+    for item in value.split_inclusive([',', ';']) {
+        let separator = item.trim_start_matches(|c| c != ',' && c != ';');
+        let item = &item[..item.len() - separator.len()];
+        let mut bounds: Vec<&str> = item
+            .split('|')
+            .flat_map(|choice| match choice.split_once("..") {
+                Some((min, max)) => vec![min, max],
+                None => vec![choice],
+            })
+            .collect();
+        bounds.dedup();
+
+        values = values
+            .iter()
+            .flat_map(|v| {
+                bounds
+                    .iter()
+                    .map(move |b| format!("{}{}{}", v, b, separator))
+            })
+            .collect();
+    }
+    values
+}
+
+fn draw_item(item: &str) -> String {
+    let choices: Vec<&str> = item.split('|').collect();
+    let choice = if choices.len() > 1 {
+        choices[rng::sample(&Uniform::new(0, choices.len()))]
+    } else {
+        item
+    };
+
+    let Some((min, max)) = choice.split_once("..") else {
+        return choice.to_string();
+    };
+    if let (Ok(min), Ok(max)) = (min.parse::<i64>(), max.parse::<i64>()) {
+        return rng::sample(&Uniform::new_inclusive(min, max)).to_string();
+    }
+    match (min.parse::<f64>(), max.parse::<f64>()) {
+        (Ok(min), Ok(max)) => rng::sample(&Uniform::new_inclusive(min, max)).to_string(),
+        _ => choice.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranges_and_choices_are_checked() {
+        assert!(is_randomized("20..150") && is_randomized("astar|prm"));
+        assert!(!is_randomized("0.05,0.0005,0.05,1.0"));
+
+        assert!(check("20..150,-1.5..1.5;a|b").is_ok());
+        assert!(check("150..20").unwrap_err().contains("is empty"));
+        assert!(check("a..20").unwrap_err().contains("invalid range"));
+        assert!(check("astar|").unwrap_err().contains("empty choice"));
+    }
+
+    #[test]
+    fn draws_stay_within_their_ranges() {
+        rng::seed(11);
+        for _ in 0..200 {
+            let n: i64 = draw("20..22").parse().unwrap();
+            assert!((20..=22).contains(&n));
+
+            let x: f64 = draw("0.01..0.05").parse().unwrap();
+            assert!((0.01..=0.05).contains(&x));
+
+            assert!(["360", "720"].contains(&draw("360|720").as_str()));
+        }
+    }
+
+    #[test]
+    fn only_the_randomized_values_of_a_list_change() {
+        rng::seed(2);
+        let drawn = draw("360|720,6.28,1000,0..0,0,0;90,3.14,1000,1,0,3.14");
+        let (first, second) = drawn.split_once(';').unwrap();
+        assert_eq!(second, "90,3.14,1000,1,0,3.14");
+
+        let values: Vec<&str> = first.split(',').collect();
+        assert!(["360", "720"].contains(&values[0]));
+        assert_eq!(values[1..], ["6.28", "1000", "0", "0", "0"]);
+    }
+
+    #[test]
+    fn extremes_combine_every_bound_and_choice() {
+        assert_eq!(extremes("20..150"), ["20", "150"]);
+        assert_eq!(extremes("astar|prm"), ["astar", "prm"]);
+        assert_eq!(
+            extremes("360|720,0..0.5;1"),
+            ["360,0;1", "360,0.5;1", "720,0;1", "720,0.5;1"]
+        );
+        assert_eq!(extremes("0..0,5"), ["0,5"]);
+    }
+
+    #[test]
+    fn draws_follow_the_seed() {
+        let values = "1..1000,0.0..1.0,a|b|c|d";
+        rng::seed(4);
+        let first: Vec<String> = (0..10).map(|_| draw(values)).collect();
+        rng::seed(4);
+        let again: Vec<String> = (0..10).map(|_| draw(values)).collect();
+        assert_eq!(first, again);
+        assert!(first.iter().any(|v| v != &first[0]));
+    }
+}
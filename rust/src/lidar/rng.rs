@@ -0,0 +1,28 @@
+use std::cell::RefCell;
+
+use rand::distributions::{Distribution, Standard};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+thread_local! {
+    // The simulation runs on Godot's main thread, so one generator serves all of it
+    static RNG: RefCell<StdRng> = RefCell::new(StdRng::from_entropy());
+}
+
+/// Reseeds the generator shared by the simulation, making the following draws reproducible.
+pub fn seed(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = StdRng::seed_from_u64(seed));
+}
+
+/// A random value from the shared generator, like `rand::random`.
+pub fn random<T>() -> T
+where
+    Standard: Distribution<T>,
+{
+    RNG.with(|rng| rng.borrow_mut().gen())
+}
+
+/// A sample of `distribution` drawn with the shared generator.
+pub fn sample<T, D: Distribution<T>>(distribution: &D) -> T {
+    RNG.with(|rng| distribution.sample(&mut *rng.borrow_mut()))
+}
@@ -0,0 +1,158 @@
+use std::str::FromStr;
+
+use crate::lidar::motion::Pose2D;
+use godot::prelude::*;
+
+/// What happens to beams that hit the robot's own body.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SelfOcclusion {
+    /// Return the range to the body, like any other obstacle.
+    Return,
+    /// Flag the beam as invalid, with a range of NaN (`null` in the JSON output).
+    Invalid,
+}
+
+impl FromStr for SelfOcclusion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "return" => Ok(SelfOcclusion::Return),
+            "invalid" => Ok(SelfOcclusion::Invalid),
+            _ => Err(format!(
+                "Unknown self occlusion mode '{}', expected one of return, invalid",
+                s
+            )),
+        }
+    }
+}
+
+/// Outline of the robot body (chassis, masts, bumpers) in the base frame, visible to its own sensors.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RobotBody {
+    pub polygon: Vec<Vector2>,
+}
+
+impl RobotBody {
+    /// A square body of side `size`, centred on the base.
+    pub fn square(size: f32) -> Self {
+        let h = size / 2.0;
+        RobotBody {
+            polygon: vec![
+                Vector2::new(h, -h),
+                Vector2::new(h, h),
+                Vector2::new(-h, h),
+                Vector2::new(-h, -h),
+            ],
+        }
+    }
+
+    /// The body outline in the world frame when the robot base is at `base`.
+    pub fn world_polygon(&self, base: Pose2D) -> Vec<Vector2> {
+        self.polygon
+            .iter()
+            .map(|v| base.position + v.rotated(base.heading))
+            .collect()
+    }
+}
+
+impl FromStr for RobotBody {
+    type Err = String;
+
+    /// Parses a polygon given as semicolon separated `x,y` vertices.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let polygon = s
+            .split(';')
+            .filter(|vertex| !vertex.trim().is_empty())
+            .map(|vertex| {
+                let values: Vec<f32> = vertex
+                    .split(',')
+                    .map(|v| v.trim().parse::<f32>())
+                    .collect::<Result<_, _>>()
+                    .map_err(|e| format!("Invalid body vertex '{}': {}", vertex, e))?;
+                match values.as_slice() {
+                    [x, y] => Ok(Vector2::new(*x, *y)),
+                    _ => Err(format!("Invalid body vertex '{}', expected x,y", vertex)),
+                }
+            })
+            .collect::<Result<Vec<Vector2>, String>>()?;
+
+        if polygon.len() < 3 {
+            return Err(format!(
+                "Invalid robot body '{}', expected at least three vertices",
+                s
+            ));
+        }
+
+        Ok(RobotBody { polygon })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lidar::geometry::ray_polygon_entry_distance;
+    use std::f32::consts::{FRAC_PI_2, TAU};
+
+    #[test]
+    fn polygons_are_parsed() {
+        let body: RobotBody = "20,-15; 20,15; -20,15; -20,-15;".parse().unwrap();
+        assert_eq!(body.polygon.len(), 4);
+        assert_eq!(body.polygon[1], Vector2::new(20.0, 15.0));
+
+        let e = "20,-15;20,15".parse::<RobotBody>().unwrap_err();
+        assert!(e.contains("at least three vertices"), "{}", e);
+        let e = "20,-15;20,a;-20,15".parse::<RobotBody>().unwrap_err();
+        assert!(e.contains("Invalid body vertex '20,a'"), "{}", e);
+        let e = "20,-15;20,15,0;-20,15".parse::<RobotBody>().unwrap_err();
+        assert!(e.contains("expected x,y"), "{}", e);
+    }
+
+    #[test]
+    fn world_polygon_follows_the_base() {
+        let body = RobotBody {
+            polygon: vec![
+                Vector2::new(20.0, 0.0),
+                Vector2::new(-10.0, 10.0),
+                Vector2::new(-10.0, -10.0),
+            ],
+        };
+        let polygon = body.world_polygon(Pose2D::new(Vector2::new(100.0, 50.0), FRAC_PI_2));
+        let expected = [
+            Vector2::new(100.0, 70.0),
+            Vector2::new(90.0, 40.0),
+            Vector2::new(110.0, 40.0),
+        ];
+        for (v, e) in polygon.iter().zip(expected.iter()) {
+            assert!(v.distance_to(*e) < 1E-4, "{:?} != {:?}", v, e);
+        }
+    }
+
+    #[test]
+    fn centred_sensors_only_see_the_body_from_outside() {
+        // The chassis of the README example with a mast sticking out of the front
+        let body: RobotBody = "20,-15;20,-3;35,-3;35,3;20,3;20,15;-20,15;-20,-15"
+            .parse()
+            .unwrap();
+        let base = Pose2D::new(Vector2::new(100.0, 50.0), FRAC_PI_2);
+        let mut reversed = body.world_polygon(base);
+        reversed.reverse();
+
+        for polygon in [body.world_polygon(base), reversed] {
+            // Beams from the centre leave through the chassis or the mast without hitting them
+            for k in 0..360 {
+                let direction = Vector2::from_angle(k as f32 * TAU / 360.0);
+                assert!(ray_polygon_entry_distance(base.position, direction, &polygon).is_none());
+            }
+
+            // A sensor in front of the mast sees its tip
+            let (distance, _) = ray_polygon_entry_distance(
+                Vector2::new(100.0, 95.0),
+                Vector2::new(0.0, -1.0),
+                &polygon,
+            )
+            .unwrap();
+            assert!((distance - 10.0).abs() < 1E-4);
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use std::fs::{remove_file, rename, File};
+use std::io::{BufWriter, Result as IoResult, Write};
+
+use ndarray::Array2;
+use serde::Serialize;
+
+/// Writes the scans of an iteration to a JSON Lines file as they are produced, each scan on its
+/// own line as a list of rows.
+///
+/// Scans are written to `<filename>.partial` and flushed one at a time, so a crash loses at most
+/// the scan being written. The file is renamed to `filename` when the iteration is finished.
+pub struct ScanWriter {
+    writer: BufWriter<File>,
+    filename: String,
+}
+
+fn partial_filename(filename: &str) -> String {
+    format!("{}.partial", filename)
+}
+
+impl ScanWriter {
+    pub fn create(filename: &str) -> IoResult<Self> {
+        let file = File::create(partial_filename(filename))?;
+        Ok(Self {
+            writer: BufWriter::new(file),
+            filename: filename.to_string(),
+        })
+    }
+
+    /// The final name of the file.
+    pub fn filename(&self) -> &str {
+        &self.filename
+    }
+
+    /// Appends one scan and flushes it to the file.
+    pub fn write_scan<T: Serialize>(&mut self, array: &Array2<T>) -> IoResult<()> {
+        let rows: Vec<Vec<&T>> = array
+            .rows()
+            .into_iter()
+            .map(|row| row.into_iter().collect())
+            .collect();
+        serde_json::to_writer(&mut self.writer, &rows)?;
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()
+    }
+
+    /// Flushes the remaining output and moves the file to its final name.
+    pub fn finish(self) -> IoResult<()> {
+        // The file is closed before renaming, which some platforms require
+        let Self { writer, filename } = self;
+        drop(writer.into_inner()?);
+        rename(partial_filename(&filename), &filename)
+    }
+
+    /// Closes and removes the file of an iteration that produced no output.
+    pub fn discard(self) -> IoResult<()> {
+        let Self { writer, filename } = self;
+        drop(writer);
+        remove_file(partial_filename(&filename))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{create_dir_all, read_to_string};
+    use std::path::Path;
+
+    fn temp_filename(name: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("scan_writer_{}", std::process::id()));
+        create_dir_all(&dir).unwrap();
+        dir.join(name).to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn scans_are_renamed_when_finished() {
+        let filename = temp_filename("finished.jsonl");
+        let mut writer = ScanWriter::create(&filename).unwrap();
+        writer
+            .write_scan(&Array2::from_shape_vec((2, 2), vec![1.0, 0.5, 2.0, 1.5]).unwrap())
+            .unwrap();
+        writer.write_scan(&Array2::from_elem((1, 2), 3.0)).unwrap();
+
+        // Each scan is on disk as soon as it is written
+        let partial = read_to_string(partial_filename(&filename)).unwrap();
+        assert_eq!(partial.lines().count(), 2);
+        assert!(!Path::new(&filename).exists());
+
+        writer.finish().unwrap();
+        assert!(!Path::new(&partial_filename(&filename)).exists());
+        let lines: Vec<serde_json::Value> = read_to_string(&filename)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                serde_json::json!([[1.0, 0.5], [2.0, 1.5]]),
+                serde_json::json!([[3.0, 3.0]])
+            ]
+        );
+    }
+
+    #[test]
+    fn discarded_scans_leave_no_file() {
+        let filename = temp_filename("discarded.jsonl");
+        let mut writer = ScanWriter::create(&filename).unwrap();
+        writer.write_scan(&Array2::<f64>::zeros((3, 2))).unwrap();
+        writer.discard().unwrap();
+
+        assert!(!Path::new(&partial_filename(&filename)).exists());
+        assert!(!Path::new(&filename).exists());
+    }
+}
@@ -0,0 +1,231 @@
+use std::f32::consts::TAU;
+use std::str::FromStr;
+
+use crate::lidar::motion::Pose2D;
+use godot::prelude::*;
+use serde::Serialize;
+
+/// Mounting pose (extrinsic) of a sensor relative to the robot base frame.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SensorMount {
+    /// Offset along the robot's heading (px).
+    pub x: f32,
+    /// Offset to the robot's right, towards +y when the heading is zero, as Godot's y axis points
+    /// down (px). The exports mirror it to -y, which is still the robot's right.
+    pub y: f32,
+    /// Rotation relative to the robot's heading (rad).
+    pub yaw: f32,
+}
+
+impl SensorMount {
+    pub fn new(x: f32, y: f32, yaw: f32) -> Self {
+        Self { x, y, yaw }
+    }
+
+    /// The mount as a pose in the base frame.
+    pub fn as_pose(&self) -> Pose2D {
+        Pose2D::new(Vector2::new(self.x, self.y), self.yaw)
+    }
+
+    /// World pose of the sensor when the robot base is at `base`.
+    pub fn sensor_pose(&self, base: Pose2D) -> Pose2D {
+        base.compose(self.as_pose())
+    }
+}
+
+impl FromStr for SensorMount {
+    type Err = String;
+
+    /// Parses a mount given as `x,y,yaw`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values: Vec<f32> = s
+            .split(',')
+            .map(|v| v.trim().parse::<f32>())
+            .collect::<Result<_, _>>()
+            .map_err(|e| format!("Invalid sensor mount '{}': {}", s, e))?;
+
+        match values.as_slice() {
+            [x, y, yaw] => Ok(SensorMount::new(*x, *y, *yaw)),
+            _ => Err(format!("Invalid sensor mount '{}', expected x,y,yaw", s)),
+        }
+    }
+}
+
+/// Beam layout and range of a scanning Lidar.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BeamModel {
+    pub n_beams: usize,
+    /// Angular field of view (rad), centred on the sensor heading unless it is a full circle.
+    pub fov: f32,
+    /// Maximum range (px).
+    pub max_range: f32,
+}
+
+impl BeamModel {
+    pub fn new(n_beams: usize, fov: f32, max_range: f32) -> Self {
+        Self {
+            n_beams,
+            fov,
+            max_range,
+        }
+    }
+
+    /// Angle of each beam relative to the sensor heading (rad).
+    ///
+    /// A full circle starts at zero and doesn't repeat the first beam; a partial field of view
+    /// spans from `-fov / 2` to `fov / 2` inclusive.
+    pub fn beam_angles(&self) -> Vec<f32> {
+        let increment = self.angle_increment();
+        (0..self.n_beams)
+            .map(|i| self.angle_min() + i as f32 * increment)
+            .collect()
+    }
+
+    fn is_full_circle(&self) -> bool {
+        self.fov >= TAU - 1E-4
+    }
+
+    /// Angle of the first beam relative to the sensor heading (rad).
+    pub fn angle_min(&self) -> f32 {
+        if self.is_full_circle() {
+            0.0
+        } else {
+            -self.fov / 2.0
+        }
+    }
+
+    /// Angle between consecutive beams (rad).
+    pub fn angle_increment(&self) -> f32 {
+        if self.is_full_circle() {
+            TAU / self.n_beams as f32
+        } else {
+            self.fov / (self.n_beams.max(2) - 1) as f32
+        }
+    }
+
+    /// Description of the scans in the `sensor_msgs/LaserScan` convention.
+    pub fn scan_info(&self, mount: SensorMount, bearing_frame: &'static str) -> ScanInfo {
+        let angle_min = self.angle_min();
+        let angle_increment = self.angle_increment();
+        ScanInfo {
+            angle_min,
+            angle_max: angle_min + self.n_beams.saturating_sub(1) as f32 * angle_increment,
+            angle_increment,
+            range_min: 0.0,
+            range_max: self.max_range,
+            n_beams: self.n_beams,
+            mount: [mount.x, mount.y, mount.yaw],
+            bearing_frame,
+        }
+    }
+}
+
+/// Beam layout of a sensor's scans: beam `i` is at `angle_min + i * angle_increment` from the
+/// sensor heading.
+#[derive(Serialize)]
+pub struct ScanInfo {
+    pub angle_min: f32,
+    pub angle_max: f32,
+    pub angle_increment: f32,
+    pub range_min: f32,
+    pub range_max: f32,
+    pub n_beams: usize,
+    /// Mounting offset x, y, yaw of the sensor on the robot base.
+    pub mount: [f32; 3],
+    /// Frame of the bearings in the returns, `sensor` or `world`.
+    pub bearing_frame: &'static str,
+}
+
+/// A Lidar on the robot: its beam model and where it is mounted.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SensorConfig {
+    pub beams: BeamModel,
+    pub mount: SensorMount,
+}
+
+impl SensorConfig {
+    /// The default single 360 beam, full circle sensor at the centre of the robot.
+    pub fn default_sensor() -> Self {
+        Self {
+            beams: BeamModel::new(360, TAU, 100000.0),
+            mount: SensorMount::new(0.0, 0.0, 0.0),
+        }
+    }
+}
+
+impl FromStr for SensorConfig {
+    type Err = String;
+
+    /// Parses a sensor given as `n_beams,fov,max_range,x,y,yaw`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values: Vec<&str> = s.split(',').map(|v| v.trim()).collect();
+        if values.len() != 6 {
+            return Err(format!(
+                "Invalid sensor '{}', expected n_beams,fov,max_range,x,y,yaw",
+                s
+            ));
+        }
+
+        let n_beams: usize = values[0]
+            .parse()
+            .map_err(|e| format!("Invalid beam count in sensor '{}': {}", s, e))?;
+        let fov: f32 = values[1]
+            .parse()
+            .map_err(|e| format!("Invalid field of view in sensor '{}': {}", s, e))?;
+        let max_range: f32 = values[2]
+            .parse()
+            .map_err(|e| format!("Invalid range in sensor '{}': {}", s, e))?;
+        let mount: SensorMount = values[3..].join(",").parse()?;
+
+        if n_beams == 0 {
+            return Err(format!("Invalid sensor '{}': needs at least one beam", s));
+        }
+        if !(fov > 0.0 && fov.is_finite()) {
+            return Err(format!(
+                "Invalid sensor '{}': the field of view must be positive",
+                s
+            ));
+        }
+        if !(max_range > 0.0 && max_range.is_finite()) {
+            return Err(format!(
+                "Invalid sensor '{}': the range must be positive",
+                s
+            ));
+        }
+
+        Ok(SensorConfig {
+            beams: BeamModel::new(n_beams, fov, max_range),
+            mount,
+        })
+    }
+}
+
+/// Parses a semicolon separated list of sensors, each given as `n_beams,fov,max_range,x,y,yaw`.
+pub fn parse_sensor_list(s: &str) -> Result<Vec<SensorConfig>, String> {
+    s.split(';')
+        .filter(|spec| !spec.trim().is_empty())
+        .map(|spec| spec.parse())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lidar::export::MetricPose;
+    use std::f32::consts::FRAC_PI_2;
+
+    #[test]
+    fn positive_y_mounts_on_the_right() {
+        // Facing up the screen, the robot's right is world +x
+        let base = Pose2D::new(Vector2::new(100.0, 100.0), -FRAC_PI_2);
+        let sensor = SensorMount::new(0.0, 10.0, 0.0).sensor_pose(base);
+        assert!((sensor.position - Vector2::new(110.0, 100.0)).length() < 1E-4);
+
+        // Mirrored, the robot faces +y and its right is still +x
+        let base = MetricPose::from_pose(base, 0.01);
+        let sensor = MetricPose::from_pose(sensor, 0.01);
+        assert!((base.yaw - FRAC_PI_2 as f64).abs() < 1E-6);
+        assert!((sensor.x - base.x - 0.1).abs() < 1E-6);
+        assert!((sensor.y - base.y).abs() < 1E-6);
+    }
+}
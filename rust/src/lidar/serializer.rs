@@ -1,9 +1,10 @@
-use ndarray::Array2;
+use ndarray::{Array2, ArrayView, Dimension};
 use serde::ser::{Serialize, Serializer};
 use serde_json::to_writer;
 use std::fs::File;
 use std::io::BufWriter;
 use std::io::Result as IoResult;
+use std::io::Write;
 
 // Wrap Array2 in a new struct
 pub struct SerializableArray2<T> {
@@ -36,3 +37,31 @@ pub fn write_to_json<T: Serialize>(filename: &str, data: &T) -> IoResult<()> {
     to_writer(writer, data)?; // Serialize the data to JSON and write it to the file
     Ok(())
 }
+
+// A function to write an array of any dimension in NumPy's .npy format (version 1.0)
+pub fn write_npy<W: Write, D: Dimension>(writer: &mut W, array: ArrayView<f64, D>) -> IoResult<()> {
+    let shape: Vec<String> = array.shape().iter().map(|n| n.to_string()).collect();
+    let shape = match shape.len() {
+        1 => format!("({},)", shape[0]), // A one element tuple needs the trailing comma
+        _ => format!("({})", shape.join(", ")),
+    };
+    let mut header = format!(
+        "{{'descr': '<f8', 'fortran_order': False, 'shape': {}, }}",
+        shape
+    );
+
+    // The magic string, version, header length and header are padded to a multiple of 64 bytes
+    let unpadded = 10 + header.len() + 1;
+    header.push_str(&" ".repeat((64 - unpadded % 64) % 64));
+    header.push('\n');
+
+    writer.write_all(b"\x93NUMPY\x01\x00")?;
+    writer.write_all(&(header.len() as u16).to_le_bytes())?;
+    writer.write_all(header.as_bytes())?;
+
+    // Elements in row-major order
+    for v in array.iter() {
+        writer.write_all(&v.to_le_bytes())?;
+    }
+    Ok(())
+}
@@ -0,0 +1,193 @@
+use std::str::FromStr;
+
+use godot::prelude::*;
+use serde::Serialize;
+
+/// The kind of trajectory the Lidar follows through the arena.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TrajectoryKind {
+    /// A single start-to-goal route from the selected planner.
+    Route,
+    /// A boustrophedon (lawnmower) sweep covering the free space.
+    Coverage,
+    /// Frontier-based exploration, choosing each goal from the map scanned so far.
+    Exploration,
+    /// Repeated tours of a set of waypoints, guaranteeing revisits for loop closure.
+    Loop,
+}
+
+impl FromStr for TrajectoryKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "route" => Ok(TrajectoryKind::Route),
+            "coverage" => Ok(TrajectoryKind::Coverage),
+            "exploration" => Ok(TrajectoryKind::Exploration),
+            "loop" => Ok(TrajectoryKind::Loop),
+            _ => Err(format!(
+                "Unknown trajectory '{}', expected one of route, coverage, exploration, loop",
+                s
+            )),
+        }
+    }
+}
+
+/// Generates boustrophedon waypoints over an `n_x` by `n_y` grid of cells.
+///
+/// Lanes run along x every `lane_spacing` rows, alternating direction. Each lane contributes the
+/// first and last cell of every free run it crosses, in sweep order.
+pub fn boustrophedon_waypoints(
+    n_x: usize,
+    n_y: usize,
+    lane_spacing: usize,
+    is_free: impl Fn(usize, usize) -> bool,
+) -> Vec<(usize, usize)> {
+    let mut waypoints = Vec::new();
+    let lane_spacing = lane_spacing.max(1);
+
+    for (lane, j) in (lane_spacing / 2..n_y).step_by(lane_spacing).enumerate() {
+        let columns: Vec<usize> = if lane % 2 == 0 {
+            (0..n_x).collect()
+        } else {
+            (0..n_x).rev().collect()
+        };
+
+        let mut run_start: Option<usize> = None;
+        let mut run_end = 0;
+        for &i in columns.iter() {
+            if is_free(i, j) {
+                if run_start.is_none() {
+                    run_start = Some(i);
+                }
+                run_end = i;
+            } else if let Some(start) = run_start.take() {
+                waypoints.push((start, j));
+                waypoints.push((run_end, j));
+            }
+        }
+        if let Some(start) = run_start {
+            waypoints.push((start, j));
+            waypoints.push((run_end, j));
+        }
+    }
+
+    waypoints
+}
+
+/// A ground-truth loop closure between two scans.
+#[derive(Serialize)]
+pub struct LoopClosure {
+    /// Index of the later scan.
+    pub scan: usize,
+    /// Index of the earlier scan it revisits.
+    pub revisited_scan: usize,
+    /// Distance between the two scan positions.
+    pub distance: f32,
+}
+
+/// Finds, for each scan, the closest earlier scan within `revisit_distance`, ignoring scans less
+/// than `min_travel` along the trajectory so that consecutive scans are not counted as revisits.
+pub fn loop_closure_pairs(
+    positions: &[Vector2],
+    revisit_distance: f32,
+    min_travel: f32,
+) -> Vec<LoopClosure> {
+    // Cumulative distance travelled up to each scan
+    let mut travelled = vec![0.0_f32; positions.len()];
+    for i in 1..positions.len() {
+        travelled[i] = travelled[i - 1] + positions[i].distance_to(positions[i - 1]);
+    }
+
+    let mut closures = Vec::new();
+    for i in 0..positions.len() {
+        let closest = (0..i)
+            .filter(|&j| travelled[i] - travelled[j] > min_travel)
+            .map(|j| (j, positions[i].distance_to(positions[j])))
+            .filter(|&(_, d)| d < revisit_distance)
+            .min_by(|a, b| a.1.total_cmp(&b.1));
+
+        if let Some((j, distance)) = closest {
+            closures.push(LoopClosure {
+                scan: i,
+                revisited_scan: j,
+                distance,
+            });
+        }
+    }
+    closures
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lanes_alternate_direction() {
+        let waypoints = boustrophedon_waypoints(10, 10, 3, |_, _| true);
+        assert_eq!(waypoints, [(0, 1), (9, 1), (9, 4), (0, 4), (0, 7), (9, 7)]);
+    }
+
+    #[test]
+    fn lanes_stop_at_obstacles() {
+        let waypoints = boustrophedon_waypoints(10, 6, 3, |i, _| i != 5);
+        assert_eq!(
+            waypoints,
+            [
+                (0, 1),
+                (4, 1),
+                (6, 1),
+                (9, 1),
+                (9, 4),
+                (6, 4),
+                (4, 4),
+                (0, 4)
+            ]
+        );
+        assert!(boustrophedon_waypoints(10, 10, 3, |_, _| false).is_empty());
+    }
+
+    /// Positions 10 px apart around a 100 px square, visited twice.
+    fn two_laps() -> Vec<Vector2> {
+        let lap = (0..40).map(|k| match k / 10 {
+            0 => Vector2::new(10.0 * (k % 10) as f32, 0.0),
+            1 => Vector2::new(100.0, 10.0 * (k % 10) as f32),
+            2 => Vector2::new(100.0 - 10.0 * (k % 10) as f32, 100.0),
+            _ => Vector2::new(0.0, 100.0 - 10.0 * (k % 10) as f32),
+        });
+        lap.clone().chain(lap).collect()
+    }
+
+    #[test]
+    fn second_lap_closes_on_the_first() {
+        let positions = two_laps();
+        let closures = loop_closure_pairs(&positions, 5.0, 100.0);
+
+        // Every scan of the second lap revisits the same point of the first
+        assert_eq!(closures.len(), 40);
+        for closure in closures.iter() {
+            assert_eq!(closure.scan, closure.revisited_scan + 40);
+            assert!(closure.distance < 1E-5);
+        }
+    }
+
+    #[test]
+    fn nearby_scans_are_not_revisits() {
+        let positions = two_laps();
+        assert!(loop_closure_pairs(&positions, 5.0, 400.0).is_empty());
+
+        // The end of the first lap comes back near its start, and the second lap closes on the
+        // closest scan of the first
+        let closures = loop_closure_pairs(&positions, 15.0, 100.0);
+        let first_lap: Vec<(usize, usize)> = closures
+            .iter()
+            .filter(|c| c.scan < 40)
+            .map(|c| (c.scan, c.revisited_scan))
+            .collect();
+        assert_eq!(first_lap, [(39, 0)]);
+        assert!(closures
+            .iter()
+            .filter(|c| c.scan >= 40)
+            .all(|c| c.scan == c.revisited_scan + 40));
+    }
+}